@@ -3,7 +3,7 @@
 use std::ffi::OsStr;
 use std::fs::File;
 use std::io::{BufReader, Read, Seek};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use ahash::HashMap;
 use anyhow::Context;
@@ -65,18 +65,18 @@ fn split_pkgname(s: &str) -> Option<&str> {
     split.next()
 }
 
-/// Get the installed size (%SIZE%) of a locally installed package.
-pub fn local_package_sizes(
-    db_path: impl AsRef<Path>,
+/// Call `f(pkgname, path)` for every package directory in `$db_path/local` whose pkgname is
+/// accepted by `filter`.
+fn for_each_local_package(
+    db_path: &Path,
     filter: impl Fn(&str) -> bool,
-) -> anyhow::Result<HashMap<String, u64>> {
-    let local_dir = db_path.as_ref().join("local");
+    mut f: impl FnMut(&str, &Path) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let local_dir = db_path.join("local");
     let dirents = local_dir
         .read_dir()
         .with_context(|| format!("failed to read directory {}", local_dir.display()))?;
 
-    let mut desc_buf = String::new();
-    let mut map = HashMap::default();
     for result in dirents {
         let entry =
             result.with_context(|| format!("failed to read dirent in {}", local_dir.display()))?;
@@ -102,25 +102,173 @@ pub fn local_package_sizes(
             continue;
         }
 
+        f(pkgname, &path)?;
+    }
+
+    Ok(())
+}
+
+/// Read a file from a local package directory into `buf`, replacing its previous contents.
+fn read_local_file(path: &Path, buf: &mut String) -> anyhow::Result<()> {
+    buf.clear();
+    File::open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?
+        .read_to_string(buf)
+        .with_context(|| format!("failed to read {}", path.display()))?;
+    Ok(())
+}
+
+/// Parse the `%FILES%` section of a `files` entry, from either the local database or a sync
+/// `.files` database.
+///
+/// We can't use [`DescIter`] here because the `files` entries in sync databases generated by
+/// `repo-add` don't end with a blank line. Directory entries (which end in `/`) are omitted.
+fn parse_files_list(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .skip_while(|line| *line != "%FILES%")
+        .skip(1)
+        .take_while(|line| !line.is_empty() && !line.starts_with('%'))
+        .filter(|file| !file.ends_with('/'))
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Open a (possibly compressed) sync database tarball.
+///
+/// The database may be gzip or zstd compressed, or not compressed at all.
+fn open_db(db_path: &Path) -> anyhow::Result<tar::Archive<Box<dyn Read>>> {
+    // read magic to determine compression type
+    let mut db_file = File::open(db_path).context("failed to open file")?;
+    let mut magic = [0u8; 4];
+    db_file.read_exact(&mut magic).context("failed to read file header")?;
+    db_file.rewind().context("failed to rewind file")?;
+
+    // Dynamic decompressor
+    let input: Box<dyn Read> = if &magic[..] == b"\x28\xb5\x2f\xfd" {
+        Box::new(zstd::Decoder::new(db_file).context("failed to initialize zstd decoder")?)
+    } else if &magic[..2] == b"\x1f\x8b" {
+        Box::new(flate2::read::GzDecoder::new(db_file))
+    } else {
+        // no recognized compression magic, assume uncompressed
+        Box::new(BufReader::new(db_file))
+    };
+
+    Ok(tar::Archive::new(input))
+}
+
+/// Call `f(pkgname, contents)` for every `$pkgname-$pkgver/$entry_name` file in a sync database
+/// whose pkgname is accepted by `filter`.
+fn for_each_db_entry(
+    db_path: &Path,
+    entry_name: &str,
+    filter: impl Fn(&str) -> bool,
+    mut f: impl FnMut(&str, &str) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let mut tarball = open_db(db_path)?;
+    let mut buf = String::new();
+
+    for result in tarball.entries().context("failed to read tar file")? {
+        let mut entry = result.context("failed to read tar entry")?;
+        // skip non-files
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+
+        // getting the path can't fail on linux
+        let path = entry.path().unwrap();
+        // skip files that aren't the ones we want
+        if path.file_name().and_then(OsStr::to_str) != Some(entry_name) {
+            continue;
+        }
+
+        // determine pkgname from the path to the entry (inside the archive)
+        let pkgname = match path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(OsStr::to_str)
+            .and_then(split_pkgname)
+        {
+            Some(pkgname) => pkgname.to_owned(),
+            None => continue,
+        };
+
+        // run the caller's filter, skip if it doesn't match
+        if !filter(&pkgname) {
+            continue;
+        }
+
+        buf.clear();
+        entry.read_to_string(&mut buf).context("failed to read tar entry data")?;
+        f(&pkgname, &buf)
+            .with_context(|| format!("failed to parse {}", entry.path().unwrap().display()))?;
+    }
+
+    Ok(())
+}
+
+/// List the files in `$db_dir/sync` with the given extension (`db` or `files`).
+fn sync_db_paths(db_dir: &Path, extension: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let sync_dir = db_dir.join("sync");
+    let dirents = sync_dir
+        .read_dir()
+        .with_context(|| format!("failed to read directory {}", sync_dir.display()))?;
+
+    let mut paths = Vec::new();
+    for result in dirents {
+        let entry =
+            result.with_context(|| format!("failed to read dirent in {}", sync_dir.display()))?;
+        let path = entry.path();
+        if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false)
+            && path.extension().and_then(OsStr::to_str) == Some(extension)
+        {
+            paths.push(path);
+        }
+    }
+
+    Ok(paths)
+}
+
+/// Read the file lists of packages in all `$db_dir/sync/*.files` databases into a pkgname->files
+/// map.
+///
+/// The `.files` databases are only present if they've been synced with `pacman -Fy`. Like
+/// [`SyncPkg::load_sync_dbs`], the order of repos in `pacman.conf` isn't respected.
+pub fn load_sync_files(
+    db_dir: impl AsRef<Path>,
+    filter: impl Fn(&str) -> bool,
+) -> anyhow::Result<HashMap<String, Vec<String>>> {
+    let mut map = HashMap::default();
+    for path in sync_db_paths(db_dir.as_ref(), "files")? {
+        for_each_db_entry(&path, "files", &filter, |pkgname, contents| {
+            map.insert(pkgname.to_owned(), parse_files_list(contents));
+            Ok(())
+        })
+        .with_context(|| format!("failed to load {}", path.display()))?;
+    }
+
+    Ok(map)
+}
+
+/// Get the installed size (%SIZE%) of a locally installed package.
+pub fn local_package_sizes(
+    db_path: impl AsRef<Path>,
+    filter: impl Fn(&str) -> bool,
+) -> anyhow::Result<HashMap<String, u64>> {
+    let mut desc_buf = String::new();
+    let mut map = HashMap::default();
+    for_each_local_package(db_path.as_ref(), filter, |pkgname, path| {
         // we care about this package, find its size
         let path = path.join("desc");
-        desc_buf.clear();
-        File::open(&path)
-            .with_context(|| format!("failed to open {}", path.display()))?
-            .read_to_string(&mut desc_buf)
-            .with_context(|| format!("failed to read {}", path.display()))?;
+        read_local_file(&path, &mut desc_buf)?;
 
         let mut size = Some(0);
         for (tag, value) in DescIter::new(&desc_buf) {
             match tag {
-                "NAME" => {
-                    if value != pkgname {
-                        size = None;
-                    }
-                }
+                "NAME" if value != pkgname => size = None,
                 "SIZE" => {
                     size = Some(value.parse().with_context(|| {
-                        "unable to parse package {dirname} size value {value:?}"
+                        format!("unable to parse package {pkgname} size value {value:?}")
                     })?);
                 }
                 _ => (),
@@ -130,7 +278,48 @@ pub fn local_package_sizes(
         if let Some(size) = size {
             map.insert(pkgname.to_owned(), size);
         }
-    }
+        Ok(())
+    })?;
+
+    Ok(map)
+}
+
+/// Get the installed version of locally installed packages, from their directory names in the
+/// local database.
+pub fn local_package_versions(
+    db_path: impl AsRef<Path>,
+    filter: impl Fn(&str) -> bool,
+) -> anyhow::Result<HashMap<String, String>> {
+    let mut map = HashMap::default();
+    for_each_local_package(db_path.as_ref(), filter, |pkgname, path| {
+        // the directory is named pkgname-pkgver-pkgrel, and was already checked to be utf8
+        let dirname = path.file_name().and_then(OsStr::to_str).unwrap_or_default();
+        if let Some(version) = dirname.strip_prefix(pkgname).and_then(|v| v.strip_prefix('-')) {
+            map.insert(pkgname.to_owned(), version.to_owned());
+        }
+        Ok(())
+    })?;
+
+    Ok(map)
+}
+
+/// Get the list of files (%FILES%) owned by a locally installed package.
+///
+/// Paths are relative to the root directory, exactly as pacman stores them. Directory entries
+/// (which end in `/`) are omitted.
+pub fn local_package_files(
+    db_path: impl AsRef<Path>,
+    filter: impl Fn(&str) -> bool,
+) -> anyhow::Result<HashMap<String, Vec<String>>> {
+    let mut files_buf = String::new();
+    let mut map = HashMap::default();
+    for_each_local_package(db_path.as_ref(), filter, |pkgname, path| {
+        let path = path.join("files");
+        read_local_file(&path, &mut files_buf)?;
+
+        map.insert(pkgname.to_owned(), parse_files_list(&files_buf));
+        Ok(())
+    })?;
 
     Ok(map)
 }
@@ -139,23 +328,37 @@ pub fn local_package_sizes(
 #[derive(Debug)]
 pub struct SyncPkg {
     pub name: String,
+    /// The pkgbase, which differs from the name for split packages
+    pub base: Option<String>,
     //pub version: String,
     pub repo: Repo,
+    /// Package file name in the repo and cache, e.g. `glibc-2.39-1-x86_64.pkg.tar.zst`
+    pub filename: String,
+    pub sha256sum: Option<String>,
     pub download_size: u64,
     pub install_size: u64,
+    /// Build time in seconds since the Unix epoch
+    pub build_date: Option<u64>,
 }
 
 impl SyncPkg {
     pub fn from_desc(desc: &str) -> anyhow::Result<Self> {
         let mut name = None;
+        let mut base = None;
         //let mut version = None;
+        let mut filename = None;
+        let mut sha256sum = None;
         let mut download_size = None;
         let mut install_size = None;
+        let mut build_date = None;
 
         for (tag, value) in DescIter::new(desc) {
             match tag {
                 "NAME" => name = Some(value.to_owned()),
+                "BASE" => base = Some(value.to_owned()),
                 //"VERSION" => version = Some(value.to_owned()),
+                "FILENAME" => filename = Some(value.to_owned()),
+                "SHA256SUM" => sha256sum = Some(value.to_owned()),
                 "CSIZE" => {
                     download_size = Some(value.parse().with_context(|| {
                         format!("failed to parse package csize {value:?} as an integer")
@@ -166,18 +369,28 @@ impl SyncPkg {
                         format!("failed to parse package isize {value:?} as an integer")
                     })?)
                 }
+                "BUILDDATE" => {
+                    build_date = Some(value.parse().with_context(|| {
+                        format!("failed to parse package builddate {value:?} as an integer")
+                    })?)
+                }
                 _ => (),
             }
         }
 
         Ok(SyncPkg {
             name: name.ok_or_else(|| anyhow::anyhow!("missing package name in desc"))?,
+            base,
             //version: version.ok_or_else(|| anyhow::anyhow!("missing package version in desc"))?,
             repo: Repo::Unknown,
+            filename: filename
+                .ok_or_else(|| anyhow::anyhow!("missing package filename in desc"))?,
+            sha256sum,
             download_size: download_size
                 .ok_or_else(|| anyhow::anyhow!("missing package download size in desc"))?,
             install_size: install_size
                 .ok_or_else(|| anyhow::anyhow!("missing package install size in desc"))?,
+            build_date,
         })
     }
 
@@ -200,64 +413,12 @@ impl SyncPkg {
             .context("db path isn't utf-8")?
             .into();
 
-        // read magic to determine compression type
-        let mut db_file = File::open(db_path).context("failed to open file")?;
-        let mut magic = [0u8; 4];
-        db_file.read_exact(&mut magic).context("failed to read file header")?;
-        db_file.rewind().context("failed to rewind file")?;
-
-        // Dynamic decompressor
-        let input: Box<dyn Read> = if &magic[..] == b"\x28\xb5\x2f\xfd" {
-            Box::new(zstd::Decoder::new(db_file).context("failed to initialize zstd decoder")?)
-        } else if &magic[..2] == b"\x1f\x8b" {
-            Box::new(flate2::read::GzDecoder::new(db_file))
-        } else {
-            // no recognized compression magic, assume uncompressed
-            Box::new(BufReader::new(db_file))
-        };
-
-        let mut tarball = tar::Archive::new(input);
-        let mut desc_buf = String::new();
-
-        for result in tarball.entries().context("failed to read tar file")? {
-            let mut entry = result.context("failed to read tar entry")?;
-            // skip non-files
-            if !entry.header().entry_type().is_file() {
-                continue;
-            }
-
-            // getting the path can't fail on linux
-            let path = entry.path().unwrap();
-            // skip files that aren't named "desc"
-            if path.file_name().and_then(OsStr::to_str) != Some("desc") {
-                continue;
-            }
-
-            // determine pkgname from the path to the desc file (inside the archive)
-            let desc_pkgname = match path
-                .parent()
-                .and_then(Path::file_name)
-                .and_then(OsStr::to_str)
-                .and_then(split_pkgname)
-            {
-                Some(pkgname) => pkgname,
-                None => continue,
-            };
-
-            // run the caller's filter, skip if it doesn't match
-            if !filter(desc_pkgname) {
-                continue;
-            }
-
-            desc_buf.clear();
-            entry.read_to_string(&mut desc_buf).context("failed to read tar entry data")?;
-            let mut pkg = SyncPkg::from_desc(&desc_buf)
-                .with_context(|| format!("failed to parse {}", entry.path().unwrap().display()))?;
+        for_each_db_entry(db_path, "desc", filter, |_, desc| {
+            let mut pkg = SyncPkg::from_desc(desc)?;
             pkg.repo = repo.clone();
             map.insert(pkg.name.clone(), pkg);
-        }
-
-        Ok(())
+            Ok(())
+        })
     }
 
     /// Read all `$db_dir/sync/*.db` files into a pkgname->SyncPkg map
@@ -271,22 +432,10 @@ impl SyncPkg {
         db_dir: impl AsRef<Path>,
         filter: impl Fn(&str) -> bool,
     ) -> anyhow::Result<HashMap<String, SyncPkg>> {
-        let sync_dir = db_dir.as_ref().join("sync");
-        let dirents = sync_dir
-            .read_dir()
-            .with_context(|| format!("failed to read directory {}", sync_dir.display()))?;
-
         let mut map = HashMap::default();
-        for result in dirents {
-            let entry = result
-                .with_context(|| format!("failed to read dirent in {}", sync_dir.display()))?;
-            let path = entry.path();
-            if entry.file_type().map(|ft| ft.is_file()).unwrap_or(false)
-                && path.extension().and_then(OsStr::to_str) == Some("db")
-            {
-                SyncPkg::read_one_db(&mut map, &path, &filter)
-                    .with_context(|| format!("failed to load {}", path.display()))?;
-            }
+        for path in sync_db_paths(db_dir.as_ref(), "db")? {
+            SyncPkg::read_one_db(&mut map, &path, &filter)
+                .with_context(|| format!("failed to load {}", path.display()))?;
         }
 
         Ok(map)
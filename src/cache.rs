@@ -0,0 +1,84 @@
+//! Look up package files in the pacman package cache
+
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+/// Compute the lowercase hex SHA256 digest of a file.
+pub fn sha256_file(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Find `filename` in the first of `cache_dirs` that has it.
+///
+/// When `sha256sum` is given, the file's contents are verified and a cached file with the wrong
+/// checksum is ignored, since pacman would download it again anyway.
+pub fn find_cached(
+    cache_dirs: &[PathBuf],
+    filename: &str,
+    sha256sum: Option<&str>,
+) -> anyhow::Result<Option<PathBuf>> {
+    for dir in cache_dirs {
+        let path = dir.join(filename);
+        if !path.is_file() {
+            continue;
+        }
+        match sha256sum {
+            Some(expected) => {
+                let actual = sha256_file(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                if actual.eq_ignore_ascii_case(expected) {
+                    return Ok(Some(path));
+                }
+            }
+            None => return Ok(Some(path)),
+        }
+    }
+    Ok(None)
+}
+
+/// Whether `filename` is a package file of version `version` of `pkgname`, for any architecture,
+/// e.g. `glibc-2.39-1-x86_64.pkg.tar.zst`. Signature files don't count.
+fn is_package_file(filename: &str, pkgname: &str, version: &str) -> bool {
+    let Some(rest) = filename
+        .strip_prefix(pkgname)
+        .and_then(|rest| rest.strip_prefix('-'))
+        .and_then(|rest| rest.strip_prefix(version))
+        .and_then(|rest| rest.strip_prefix('-'))
+    else {
+        return false;
+    };
+    // what's left is the architecture, which can't contain a dash, and the extension
+    match rest.split_once(".pkg.tar") {
+        Some((arch, ext)) => !arch.is_empty() && !arch.contains('-') && !ext.ends_with(".sig"),
+        None => false,
+    }
+}
+
+/// The package files in `cache_dirs`, to look up with `find_version`
+pub fn list_packages(cache_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    cache_dirs
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.file_name().is_some_and(|n| n.to_string_lossy().contains(".pkg.tar")))
+        .collect()
+}
+
+/// Find a package file of version `version` of `pkgname` in `packages` from `list_packages`
+pub fn find_version<'a>(packages: &'a [PathBuf], pkgname: &str, version: &str) -> Option<&'a Path> {
+    packages
+        .iter()
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| is_package_file(name, pkgname, version))
+        })
+        .map(PathBuf::as_path)
+}
@@ -0,0 +1,350 @@
+//! The config file, `$XDG_CONFIG_HOME/checkupgrades/config.toml`
+//!
+//! Each setting is the default for the command line option of the same name, so options given on
+//! the command line always win, and the file's values are validated by the same parsers. Lists
+//! like `sort` and `columns` are replaced, not extended, by the command line. Each flag has a
+//! negation to undo the file's value, like `--no-group` for `group = true`, or `--sync` for
+//! `sync = false`.
+//!
+//! ```toml
+//! format = "text"
+//! sort = ["download:desc", "name"]
+//! columns = ["class", "download"]
+//! units = "auto"
+//! min-age = "2d"
+//! ignore = ["linux*"]
+//!
+//! [min-age-for]
+//! firefox = "0"
+//!
+//! [hooks]
+//! new = 'notify-send "New upgrades" "$CHECKUPGRADES_NEW new, $CHECKUPGRADES_COUNT in total"'
+//!
+//! [theme]
+//! "repo/*-testing" = "1;33"
+//! new = "38;5;42"
+//! ```
+
+use std::collections::BTreeMap;
+use std::error::Error as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+use serde::{Deserialize, Serialize};
+
+/// Overrides the path of the config file
+pub const ENV_VAR: &str = "CHECKUPGRADES_CONFIG";
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Where to keep the copy of the sync databases
+    pub db_path: Option<PathBuf>,
+    pub color: Option<String>,
+    pub paging: Option<String>,
+    pub format: Option<String>,
+    pub sort: Option<Vec<String>>,
+    pub only: Option<Vec<String>>,
+    /// Globs of package names to leave out, like pacman's `IgnorePkg`
+    pub ignore: Option<Vec<String>>,
+    /// Refresh the copy of the sync databases before checking
+    pub sync: Option<bool>,
+    pub many: Option<usize>,
+    /// Also write Prometheus metrics to this file
+    pub prom_file: Option<PathBuf>,
+    pub columns: Option<Vec<String>>,
+    /// Record each run's upgrades to mark new ones
+    pub history: Option<bool>,
+    /// Report what pacman's log says about the pending packages
+    pub log: Option<bool>,
+    pub log_file: Option<PathBuf>,
+    pub units: Option<String>,
+    pub si: Option<bool>,
+    pub width: Option<usize>,
+    pub group: Option<bool>,
+    pub expand: Option<bool>,
+    pub by_repo: Option<bool>,
+    pub min_age: Option<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub min_age_for: BTreeMap<String, String>,
+    pub verify_cache: Option<bool>,
+    pub require_space: Option<bool>,
+    pub rollback: Option<bool>,
+    pub require_rollback: Option<bool>,
+    /// Match upgrades against the security tracker
+    pub security: Option<bool>,
+    pub security_file: Option<PathBuf>,
+    pub security_only: Option<bool>,
+    /// Show unread Arch news since the last full upgrade
+    pub news: Option<bool>,
+    pub news_file: Option<PathBuf>,
+    pub ok_to_upgrade: Option<bool>,
+    pub download_dir: Option<PathBuf>,
+    /// Events and the commands to run for them, see the `hooks` module
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub hooks: BTreeMap<String, String>,
+    /// Theme keys and their SGR styles, see the `theme` module
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub theme: BTreeMap<String, String>,
+}
+
+/// The settings which are flags, by the id of the flag turning them on
+const FLAGS: [&str; 15] = [
+    "sync",
+    "history",
+    "log",
+    "si",
+    "group",
+    "expand",
+    "by-repo",
+    "verify-cache",
+    "require-space",
+    "rollback",
+    "require-rollback",
+    "security",
+    "security-only",
+    "news",
+    "ok-to-upgrade",
+];
+
+/// Add the missing half of each flag and its `no-` negation to `cmd`, hidden and overriding the
+/// other, so the command line can undo a flag set in the config file. `sync` and `history` are
+/// on by default, the others off.
+pub fn add_negations(mut cmd: Command) -> Command {
+    for id in FLAGS {
+        let negation = format!("no-{id}");
+        let (missing, existing) = if cmd.get_arguments().any(|arg| arg.get_id() == id) {
+            (negation.clone(), id.to_owned())
+        } else {
+            (id.to_owned(), negation.clone())
+        };
+        let arg = Arg::new(missing.clone())
+            .long(missing.clone())
+            .action(ArgAction::SetTrue)
+            .overrides_with(existing.clone())
+            .hide(true);
+        let arg = if missing == id { arg.default_value("true") } else { arg };
+        cmd = cmd.arg(arg).mut_arg(existing, |arg| arg.overrides_with(missing));
+    }
+    cmd
+}
+
+/// Whether the flag `id` is on. The config file sets the default of `id`, so its negation given on
+/// the command line wins over that.
+pub fn flag(args: &ArgMatches, id: &str) -> bool {
+    let negation = format!("no-{id}");
+    args.value_source(&negation) != Some(ValueSource::CommandLine) && args.get_flag(id)
+}
+
+/// The path of the config file, `$CHECKUPGRADES_CONFIG` or in the XDG config dir
+pub fn path() -> PathBuf {
+    match std::env::var_os(ENV_VAR) {
+        Some(path) => PathBuf::from(path),
+        None => crate::xdg_dir("XDG_CONFIG_HOME", ".config").join("checkupgrades/config.toml"),
+    }
+}
+
+impl Config {
+    /// Load the config file at `path`. A missing file is the same as an empty one.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        toml::from_str(&contents).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// The values of the config which are defaults for command line args, by arg id
+    fn arg_defaults(&self) -> Vec<(&'static str, Vec<String>)> {
+        let one = |value: &Option<String>| value.clone().map(|v| vec![v]);
+        let flag = |value: Option<bool>| value.map(|v| vec![v.to_string()]);
+        let path = |value: &Option<PathBuf>| {
+            value.as_ref().map(|p| vec![p.to_string_lossy().into_owned()])
+        };
+        let min_age_for = (!self.min_age_for.is_empty()).then(|| {
+            self.min_age_for.iter().map(|(pkgname, age)| format!("{pkgname}={age}")).collect()
+        });
+        let hooks = (!self.hooks.is_empty()).then(|| {
+            self.hooks.iter().map(|(event, command)| format!("{event}={command}")).collect()
+        });
+        [
+            ("color", one(&self.color)),
+            ("paging", one(&self.paging)),
+            ("format", one(&self.format)),
+            ("sort", self.sort.clone()),
+            ("only", self.only.clone()),
+            ("ignore", self.ignore.clone()),
+            ("sync", flag(self.sync)),
+            ("many", self.many.map(|many| vec![many.to_string()])),
+            ("prom-file", path(&self.prom_file)),
+            ("columns", self.columns.clone()),
+            ("history", flag(self.history)),
+            ("log", flag(self.log)),
+            ("log-file", path(&self.log_file)),
+            ("units", one(&self.units)),
+            ("si", flag(self.si)),
+            ("width", self.width.map(|w| vec![w.to_string()])),
+            ("group", flag(self.group)),
+            ("expand", flag(self.expand)),
+            ("by-repo", flag(self.by_repo)),
+            ("min-age", one(&self.min_age)),
+            ("min-age-for", min_age_for),
+            ("verify-cache", flag(self.verify_cache)),
+            ("require-space", flag(self.require_space)),
+            ("rollback", flag(self.rollback)),
+            ("require-rollback", flag(self.require_rollback)),
+            ("security", flag(self.security)),
+            ("security-file", path(&self.security_file)),
+            ("security-only", flag(self.security_only)),
+            ("news", flag(self.news)),
+            ("news-file", path(&self.news_file)),
+            ("ok-to-upgrade", flag(self.ok_to_upgrade)),
+            ("download-dir", path(&self.download_dir)),
+            ("hook", hooks),
+        ]
+        .into_iter()
+        .filter_map(|(id, values)| Some((id, values?)))
+        .collect()
+    }
+
+    /// Check the config's values with the parsers of `cmd`'s args. Clap would report a bad
+    /// default as if it was given on the command line, without mentioning the file.
+    pub fn validate(&self, cmd: &Command) -> Result<()> {
+        for (id, values) in self.arg_defaults() {
+            let key = if id == "hook" { "hooks" } else { id };
+            let probe = cmd.clone().mut_arg(id, |arg| arg.default_values(&values));
+            let Err(err) = probe.try_get_matches_from([cmd.get_name()]) else {
+                continue;
+            };
+            // other errors, like a missing required option, are about how options combine
+            if !matches!(err.kind(), ErrorKind::InvalidValue | ErrorKind::ValueValidation) {
+                continue;
+            }
+            let value = match err.get(ContextKind::InvalidValue) {
+                Some(ContextValue::String(value)) => format!(" = {value:?}"),
+                _ => String::new(),
+            };
+            match (err.source(), err.get(ContextKind::ValidValue)) {
+                (Some(source), _) => bail!("{key}{value}: {source}"),
+                (None, Some(ContextValue::Strings(valid))) => {
+                    bail!("{key}{value}, expected one of {}", valid.join(", "))
+                }
+                (None, _) => bail!("{key}{value}: {}", err.kind()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Make the config's values the defaults of `cmd`'s args
+    pub fn apply_defaults(&self, mut cmd: Command) -> Command {
+        for (id, values) in self.arg_defaults() {
+            cmd = cmd.mut_arg(id, |arg| arg.default_values(values));
+        }
+        cmd
+    }
+
+    /// The effective settings from parsed args, which include the config's values as defaults.
+    /// `db_path` and `theme` aren't args, so they're passed in.
+    pub fn effective(args: &ArgMatches, db_path: &Path, theme: BTreeMap<String, String>) -> Self {
+        let raw = |id: &str| -> Option<Vec<String>> {
+            let values = args.get_raw(id)?;
+            Some(values.map(|v| v.to_string_lossy().into_owned()).collect())
+        };
+        let one = |id: &str| raw(id).and_then(|values| values.into_iter().next());
+        Self {
+            db_path: Some(db_path.to_owned()),
+            color: if args.get_flag("no-color") { Some("never".to_owned()) } else { one("color") },
+            paging: one("paging"),
+            format: one("format"),
+            sort: raw("sort"),
+            only: raw("only"),
+            ignore: raw("ignore"),
+            sync: Some(flag(args, "sync")),
+            many: one("many").and_then(|many| many.parse().ok()),
+            prom_file: one("prom-file").map(PathBuf::from),
+            columns: raw("columns"),
+            history: Some(flag(args, "history")),
+            log: Some(flag(args, "log")),
+            log_file: one("log-file").map(PathBuf::from),
+            units: one("units"),
+            si: Some(flag(args, "si")),
+            width: one("width").and_then(|w| w.parse().ok()),
+            group: Some(flag(args, "group")),
+            expand: Some(flag(args, "expand")),
+            by_repo: Some(flag(args, "by-repo")),
+            min_age: one("min-age"),
+            min_age_for: raw("min-age-for")
+                .into_iter()
+                .flatten()
+                .filter_map(|o| o.split_once('=').map(|(k, v)| (k.to_owned(), v.to_owned())))
+                .collect(),
+            verify_cache: Some(flag(args, "verify-cache")),
+            require_space: Some(flag(args, "require-space")),
+            rollback: Some(flag(args, "rollback")),
+            require_rollback: Some(flag(args, "require-rollback")),
+            security: Some(flag(args, "security")),
+            security_file: one("security-file").map(PathBuf::from),
+            security_only: Some(flag(args, "security-only")),
+            news: Some(flag(args, "news")),
+            news_file: one("news-file").map(PathBuf::from),
+            ok_to_upgrade: Some(flag(args, "ok-to-upgrade")),
+            download_dir: one("download-dir").map(PathBuf::from),
+            hooks: raw("hook")
+                .filter(|_| !args.get_flag("no-hooks"))
+                .into_iter()
+                .flatten()
+                .filter_map(|h| h.split_once('=').map(|(k, v)| (k.to_owned(), v.to_owned())))
+                .collect(),
+            theme,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_flags_override_config() {
+        for id in FLAGS {
+            for value in [true, false] {
+                let config: Config = toml::from_str(&format!("{id} = {value}")).unwrap();
+                let cmd = crate::Args::command();
+                config.validate(&cmd).unwrap();
+                let cmd = config.apply_defaults(cmd);
+                let args = cmd.clone().try_get_matches_from(["checkupgrades"]).unwrap();
+                assert_eq!(flag(&args, id), value, "{id} = {value}");
+
+                let opposite = if value { format!("--no-{id}") } else { format!("--{id}") };
+                let mut argv = vec!["checkupgrades", &opposite];
+                if id == "expand" {
+                    argv.push("--group");
+                }
+                let args = cmd.try_get_matches_from(argv).unwrap();
+                assert_eq!(flag(&args, id), !value, "{id} = {value}, {opposite}");
+            }
+        }
+    }
+
+    #[test]
+    fn last_of_flag_and_negation_wins() {
+        let cmd = crate::Args::command();
+        for (argv, group) in [
+            (["checkupgrades", "--group", "--no-group"], false),
+            (["checkupgrades", "--no-group", "--group"], true),
+        ] {
+            let args = cmd.clone().try_get_matches_from(argv).unwrap();
+            assert_eq!(flag(&args, "group"), group, "{argv:?}");
+        }
+        let args = cmd.try_get_matches_from(["checkupgrades", "--no-sync", "--sync"]).unwrap();
+        assert!(flag(&args, "sync"));
+    }
+}
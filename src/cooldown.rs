@@ -0,0 +1,116 @@
+//! Upgrade "cooldown" policy: hold back packages which were built too recently
+//!
+//! Waiting a few days after a package is published gives time for regressions to be found and
+//! fixed before we install it. The age is measured from the package's `%BUILDDATE%`.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ahash::HashMap;
+use anyhow::{anyhow, Context};
+
+pub const HOUR: u64 = 60 * 60;
+pub const DAY: u64 = 24 * HOUR;
+
+/// The current time in seconds since the Unix epoch
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+/// Parse an age like `3d`, `12h`, `1w`, or `90m` into seconds. A number without a unit is a
+/// number of days.
+pub fn parse_age(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: u64 = num.parse().with_context(|| format!("invalid age {s:?}"))?;
+    let scale = match unit {
+        "" | "d" => DAY,
+        "w" => 7 * DAY,
+        "h" => HOUR,
+        "m" => 60,
+        "s" => 1,
+        _ => return Err(anyhow!("invalid age unit {unit:?}, expected one of w, d, h, m, s")),
+    };
+    num.checked_mul(scale).ok_or_else(|| anyhow!("age {s:?} is too large"))
+}
+
+/// Parse a per-package override like `linux=7d`
+pub fn parse_override(s: &str) -> anyhow::Result<(String, u64)> {
+    let (pkgname, age) =
+        s.split_once('=').ok_or_else(|| anyhow!("expected PKGNAME=AGE, got {s:?}"))?;
+    Ok((pkgname.to_owned(), parse_age(age)?))
+}
+
+/// Describe a positive number of seconds in whole days, or hours when less than a day
+pub fn describe_wait(secs: u64) -> String {
+    if secs >= DAY {
+        let days = secs.div_ceil(DAY);
+        format!("{days} day{}", if days == 1 { "" } else { "s" })
+    } else {
+        let hours = secs.div_ceil(HOUR).max(1);
+        format!("{hours} hour{}", if hours == 1 { "" } else { "s" })
+    }
+}
+
+/// Describe how long ago something happened, `secs` seconds ago, rounded down to whole days or
+/// hours
+pub fn describe_elapsed(secs: u64) -> String {
+    let plural = |n: u64| if n == 1 { "" } else { "s" };
+    if secs >= DAY {
+        let days = secs / DAY;
+        format!("{days} day{} ago", plural(days))
+    } else if secs >= HOUR {
+        let hours = secs / HOUR;
+        format!("{hours} hour{} ago", plural(hours))
+    } else {
+        "less than an hour ago".to_owned()
+    }
+}
+
+/// The minimum package age, with per-package overrides
+#[derive(Debug, Default, Clone)]
+pub struct Policy {
+    min_age: u64,
+    overrides: HashMap<String, u64>,
+}
+
+impl Policy {
+    pub fn new(min_age: u64, overrides: impl IntoIterator<Item = (String, u64)>) -> Self {
+        Self { min_age, overrides: overrides.into_iter().collect() }
+    }
+
+    /// The minimum age for `pkgname`, in seconds
+    pub fn min_age(&self, pkgname: &str) -> u64 {
+        self.overrides.get(pkgname).copied().unwrap_or(self.min_age)
+    }
+
+    /// The time at which an upgrade to a package built at `build_date` is old enough to install
+    pub fn ready_at(&self, pkgname: &str, build_date: u64) -> u64 {
+        build_date.saturating_add(self.min_age(pkgname))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ages() {
+        assert_eq!(parse_age("3").unwrap(), 3 * DAY);
+        assert_eq!(parse_age("2w").unwrap(), 14 * DAY);
+        assert_eq!(parse_age("12h").unwrap(), 12 * HOUR);
+        assert_eq!(parse_age("90m").unwrap(), 90 * 60);
+        assert_eq!(parse_age("0").unwrap(), 0);
+        assert!(parse_age("3y").is_err());
+        assert!(parse_age("h").is_err());
+        assert!(parse_age("99999999999999w").is_err());
+        assert!(parse_age("99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn huge_min_age_is_never_ready() {
+        let policy = Policy::new(u64::MAX, [("linux".to_owned(), DAY)]);
+        assert_eq!(policy.ready_at("glibc", 1_700_000_000), u64::MAX);
+        assert_eq!(policy.ready_at("linux", 1_700_000_000), 1_700_000_000 + DAY);
+    }
+}
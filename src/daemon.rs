@@ -0,0 +1,329 @@
+//! A background daemon which keeps the pending upgrades in memory and answers queries on a socket
+//!
+//! `checkupgrades daemon` syncs the copy of the databases every `--interval`, so any number of
+//! status bars can poll it as often as they like without syncing themselves. It runs the hooks
+//! after each sync, with upgrades counting as new when the previous sync didn't find them.
+//! Clients connect to the Unix socket, send one query on a line, and read the answer until the
+//! daemon closes the connection. Failed queries are answered with a line starting with `error: `.
+//! Besides `checkupgrades query`, anything that can talk to a Unix socket works:
+//!
+//! ```sh
+//! echo count | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/checkupgrades.sock
+//! ```
+
+use std::fs::{self, Permissions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anstream::{AutoStream, ColorChoice};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::builder::PossibleValue;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{cooldown, hooks, output, sort, statusbar, warning, Args, Input, Totals, Upgrade};
+
+/// How long a client may take to send its query
+const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The default socket path, in `$XDG_RUNTIME_DIR` or else in the temporary directory
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("checkupgrades.sock"),
+        None => {
+            let uid = rustix::process::getuid().as_raw();
+            let dir = std::env::var_os("TMPDIR").map_or_else(|| PathBuf::from("/tmp"), From::from);
+            dir.join(format!("checkupgrades-{uid}.sock"))
+        }
+    }
+}
+
+/// The queries the daemon answers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Query {
+    List,
+    Count,
+    Json,
+    Waybar,
+    I3blocks,
+    LastSync,
+    Refresh,
+}
+
+impl ValueEnum for Query {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            Self::List,
+            Self::Count,
+            Self::Json,
+            Self::Waybar,
+            Self::I3blocks,
+            Self::LastSync,
+            Self::Refresh,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::List => PossibleValue::new("list").help("The list of upgrades, without colors"),
+            Self::Count => PossibleValue::new("count").help("The number of upgrades"),
+            Self::Json => PossibleValue::new("json")
+                .help("JSON document with the upgrades, totals, and time of the last sync"),
+            Self::Waybar => PossibleValue::new("waybar").help("Like --format waybar"),
+            Self::I3blocks => PossibleValue::new("i3blocks").help("Like --format i3blocks"),
+            Self::LastSync => PossibleValue::new("last-sync")
+                .help("When the databases were last synced, in seconds since the Unix epoch"),
+            Self::Refresh => PossibleValue::new("refresh")
+                .help("Sync now, answering once it's done, then like count"),
+        })
+    }
+}
+
+/// The upgrades found by the last sync, shared between the syncing and the answering threads
+#[derive(Debug, Default)]
+struct State {
+    /// The upgrades found by the last successful sync, filtered and sorted
+    upgrades: Vec<Upgrade>,
+    /// When the last successful sync finished
+    last_sync: Option<u64>,
+    /// Why the last sync failed, if it did
+    error: Option<String>,
+    /// The number of syncs started
+    started: u64,
+    /// The number of syncs done, successful or not
+    syncs: u64,
+    /// The number of the sync clients are waiting for, which started after they asked. Syncing
+    /// is due while it's past `started`.
+    refresh: u64,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    /// Notified when a sync finishes or a client asks for one
+    changed: Condvar,
+}
+
+/// The document answering the `json` query
+#[derive(Serialize)]
+struct JsonAnswer<'a> {
+    upgrades: &'a [Upgrade],
+    cooling_down: &'a [Upgrade],
+    totals: &'a Totals,
+    last_sync: Option<u64>,
+}
+
+/// Bind the socket at `path`, replacing a stale one left by a daemon which didn't exit cleanly
+fn bind(path: &Path) -> Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        bail!("another daemon is already listening on {}", path.display());
+    }
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => {
+            return Err(err).with_context(|| format!("failed to remove {}", path.display()));
+        }
+        _ => {}
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {}", dir.display()))?;
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to listen on {}", path.display()))?;
+    fs::set_permissions(path, Permissions::from_mode(0o600))
+        .with_context(|| format!("failed to set permissions of {}", path.display()))?;
+    Ok(listener)
+}
+
+/// Sync and compute the upgrades like a normal run does, up to where they're split by cooldown
+fn sync(args: &Args) -> Result<Vec<Upgrade>> {
+    let mut upgrades = crate::load_upgrades(args)?;
+    if !args.only.is_empty() {
+        upgrades.retain(|u| args.only.contains(&u.class));
+    }
+    if args.security_only {
+        upgrades.retain(|u| !u.security.is_empty());
+    }
+    sort::sort_upgrades(&mut upgrades, &args.sort);
+    for u in upgrades.iter_mut() {
+        u.ready_at = u.build_date.map(|date| args.cooldown.ready_at(&u.pkgname, date));
+    }
+    Ok(upgrades)
+}
+
+/// Run the daemon, syncing every `interval` seconds and serving queries on `socket`
+pub fn serve(mut args: Args, interval: u64, socket: &Path) -> Result<()> {
+    if matches!(args.input, Input::Stdin) {
+        bail!("the daemon can't read the upgrades from stdin");
+    }
+    // syncing is what the daemon is for, without it every answer would come from the same
+    // databases, so `--no-sync` and `sync = false` in the config only apply to normal runs
+    args.sync = true;
+    let listener = bind(socket)?;
+    let args = Arc::new(args);
+    let shared = Arc::new(Shared::default());
+    {
+        let (args, shared) = (Arc::clone(&args), Arc::clone(&shared));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        warning!("failed to accept a connection: {err}");
+                        continue;
+                    }
+                };
+                let (args, shared) = (Arc::clone(&args), Arc::clone(&shared));
+                thread::spawn(move || {
+                    if let Err(err) = handle(stream, &args, &shared) {
+                        warning!("failed to answer a query: {err:#}");
+                    }
+                });
+            }
+        });
+    }
+
+    loop {
+        let number = {
+            let mut state = shared.state.lock().unwrap();
+            state.started += 1;
+            state.started
+        };
+        let result = sync(&args).map(|mut upgrades| {
+            // new means not found by the previous sync, like a run compared to the history
+            let state = shared.state.lock().unwrap();
+            if state.last_sync.is_some() {
+                for u in upgrades.iter_mut() {
+                    u.new = !state
+                        .upgrades
+                        .iter()
+                        .any(|old| old.pkgname == u.pkgname && old.newver == u.newver);
+                }
+            }
+            upgrades
+        });
+        if let (Ok(upgrades), Input::None) = (&result, &args.input) {
+            let now = cooldown::now();
+            let ready: Vec<Upgrade> =
+                upgrades.iter().filter(|u| u.is_ready(now)).cloned().collect();
+            hooks::run_all(&args.hooks, &ready);
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        match result {
+            Ok(upgrades) => {
+                state.upgrades = upgrades;
+                state.last_sync = Some(cooldown::now());
+                state.error = None;
+            }
+            Err(err) => {
+                warning!("failed to sync: {err:#}");
+                state.error = Some(format!("{err:#}"));
+            }
+        }
+        state.syncs = number;
+        shared.changed.notify_all();
+
+        let deadline = Instant::now() + Duration::from_secs(interval);
+        while state.refresh <= state.started {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            if timeout.is_zero() {
+                break;
+            }
+            state = shared.changed.wait_timeout(state, timeout).unwrap().0;
+        }
+    }
+}
+
+/// Read one query from `stream` and write the answer
+fn handle(stream: UnixStream, args: &Args, shared: &Shared) -> Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let answer = Query::from_str(line.trim(), true)
+        .map_err(|_| anyhow!("unknown query {:?}", line.trim()))
+        .and_then(|query| answer(query, args, shared));
+    let mut stream = &stream;
+    match answer {
+        Ok(answer) => stream.write_all(&answer)?,
+        Err(err) => writeln!(stream, "error: {err:#}")?,
+    }
+    Ok(())
+}
+
+/// Answer `query`, waiting for the first sync or the requested one
+fn answer(query: Query, args: &Args, shared: &Shared) -> Result<Vec<u8>> {
+    let mut state = shared.state.lock().unwrap();
+    if query == Query::Refresh {
+        // a sync already running may have read the databases before they changed
+        let wanted = state.started + 1;
+        state.refresh = state.refresh.max(wanted);
+        shared.changed.notify_all();
+        state = shared.changed.wait_while(state, |state| state.syncs < wanted).unwrap();
+        if let Some(err) = &state.error {
+            bail!("{err}");
+        }
+    } else {
+        state = shared.changed.wait_while(state, |state| state.syncs == 0).unwrap();
+    }
+    let Some(last_sync) = state.last_sync else {
+        bail!("{}", state.error.as_deref().unwrap_or("no sync yet"));
+    };
+
+    let now = cooldown::now();
+    let (upgrades, cooling): (Vec<Upgrade>, Vec<Upgrade>) =
+        state.upgrades.iter().cloned().partition(|u| u.is_ready(now));
+    drop(state);
+
+    let mut out = AutoStream::new(Vec::new(), ColorChoice::Never);
+    match query {
+        Query::List => {
+            let opts = output::TextOptions { width: None, ..args.text.clone() };
+            output::write_list(&mut out, &upgrades, &cooling, now, &opts)?;
+        }
+        Query::Count | Query::Refresh => writeln!(out, "{}", upgrades.len())?,
+        Query::Json => {
+            let doc = JsonAnswer {
+                upgrades: &upgrades,
+                cooling_down: &cooling,
+                totals: &Totals::of(&upgrades),
+                last_sync: Some(last_sync),
+            };
+            serde_json::to_writer_pretty(&mut out, &doc)?;
+            writeln!(out)?;
+        }
+        Query::Waybar => {
+            let tooltip = statusbar::tooltip(&upgrades, &cooling, now, &args.text)?;
+            let classes = statusbar::classes(&upgrades, args.many);
+            statusbar::write_waybar(&mut out, &upgrades, &tooltip, &classes)?;
+        }
+        Query::I3blocks => {
+            let classes = statusbar::classes(&upgrades, args.many);
+            statusbar::write_i3blocks(&mut out, &upgrades, &classes)?;
+        }
+        Query::LastSync => writeln!(out, "{last_sync}")?,
+    }
+    Ok(out.into_inner())
+}
+
+/// Send `query` to the daemon listening on `socket` and print the answer
+pub fn query(socket: &Path, query: Query) -> Result<()> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("failed to connect to the daemon at {}", socket.display()))?;
+    writeln!(stream, "{}", query.to_possible_value().unwrap().get_name())?;
+    stream.shutdown(Shutdown::Write)?;
+    let answer = io::read_to_string(&stream).context("failed to read the answer")?;
+    if let Some(err) = answer.strip_prefix("error: ") {
+        bail!("the daemon failed to answer: {}", err.trim_end());
+    }
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(answer.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! Check whether an upgrade fits in the free space of the filesystems it touches
+
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+/// Space needed and available on one filesystem
+#[derive(Debug)]
+pub struct FsSpace {
+    /// The first path given for this filesystem
+    pub path: PathBuf,
+    /// Total bytes needed by everything on this filesystem
+    pub needed: u64,
+    /// Bytes available to unprivileged users
+    pub available: u64,
+}
+
+impl FsSpace {
+    pub fn fits(&self) -> bool {
+        self.needed <= self.available
+    }
+}
+
+/// Find the closest existing ancestor of `path` (or itself), so we can check a directory that
+/// hasn't been created yet.
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors().find(|p| p.exists()).unwrap_or(Path::new("/"))
+}
+
+/// Compute the space needed on each filesystem, given a list of paths and how many bytes will be
+/// written under each of them.
+///
+/// Paths which are on the same filesystem have their requirements added together, so e.g. a cache
+/// directory on the root filesystem needs room for both the downloads and the installed files.
+pub fn check(needs: &[(&Path, u64)]) -> anyhow::Result<Vec<FsSpace>> {
+    let mut result: Vec<(u64, FsSpace)> = Vec::new();
+    for &(path, needed) in needs {
+        let existing = existing_ancestor(path);
+        // f_fsid is 0 on tmpfs and overlayfs with some kernels, so the device tells filesystems
+        // apart instead
+        let dev = rustix::fs::stat(existing)
+            .with_context(|| format!("failed to stat {}", path.display()))?
+            .st_dev;
+        match result.iter_mut().find(|(d, _)| *d == dev) {
+            Some((_, space)) => space.needed += needed,
+            None => {
+                let stat = rustix::fs::statvfs(existing)
+                    .with_context(|| format!("failed to statvfs {}", path.display()))?;
+                let available = stat.f_bavail * stat.f_frsize;
+                result.push((dev, FsSpace { path: path.to_owned(), needed, available }));
+            }
+        }
+    }
+    Ok(result.into_iter().map(|(_, space)| space).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_filesystem_adds_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("not/created/yet");
+        let spaces = check(&[(dir.path(), 10), (&missing, 5)]).unwrap();
+        assert_eq!(spaces.len(), 1);
+        assert_eq!(spaces[0].path, dir.path());
+        assert_eq!(spaces[0].needed, 15);
+    }
+
+    #[test]
+    fn separate_filesystems() {
+        // /proc is never on the same filesystem as the root
+        let spaces = check(&[(Path::new("/"), 1), (Path::new("/proc"), 2)]).unwrap();
+        let needed: Vec<u64> = spaces.iter().map(|s| s.needed).collect();
+        assert_eq!(needed, [1, 2]);
+    }
+}
@@ -0,0 +1,240 @@
+//! Download upgrade packages ahead of time, without root
+//!
+//! Packages are fetched from the repo servers configured in pacman.conf into a user-owned cache
+//! directory. Adding that directory as a `CacheDir` in pacman.conf lets `pacman -Su` install
+//! everything without downloading again.
+//!
+//! Transfers are done with `curl` (which pacman already depends on) so that partial downloads can
+//! be resumed, and signatures are checked with `gpgv` against pacman's keyring.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::SystemTime;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+use crate::size::SizeFormat;
+use crate::{cache, pacman_conf, warning, Upgrade};
+
+/// The keyring pacman uses to verify package signatures
+const PACMAN_KEYRING: &str = "/etc/pacman.d/gnupg/pubring.gpg";
+
+/// Fetch `url` into `dest` with curl, resuming if `dest` already exists.
+fn curl(url: &str, dest: &Path, progress: bool) -> Result<()> {
+    let mut cmd = Command::new("curl");
+    cmd.args(["--fail", "--location", "--continue-at", "-", "--output"]).arg(dest).arg(url);
+    if progress {
+        cmd.arg("--progress-bar");
+    } else {
+        cmd.arg("--silent");
+    }
+    let status = cmd.stdin(Stdio::null()).status().context("failed to execute curl")?;
+    if !status.success() {
+        bail!("curl failed to download {url} ({status})");
+    }
+    Ok(())
+}
+
+/// The age of `path` in seconds, `None` if it doesn't exist
+fn file_age(path: &Path) -> Option<u64> {
+    let modified = fs::metadata(path).ok()?.modified().ok()?;
+    Some(SystemTime::now().duration_since(modified).map_or(0, |age| age.as_secs()))
+}
+
+/// Fetch a small file like a feed from `url` into `$XDG_CACHE_HOME/checkupgrades/{name}`, unless
+/// the copy there is younger than `max_age` seconds, and return its path. If fetching fails, an
+/// existing copy is used with a warning.
+pub fn fetch_cached(url: &str, name: &str, max_age: u64) -> Result<PathBuf> {
+    let path = crate::xdg_dir("XDG_CACHE_HOME", ".cache").join("checkupgrades").join(name);
+    let age = file_age(&path);
+    if age.is_some_and(|age| age < max_age) {
+        return Ok(path);
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {}", dir.display()))?;
+    }
+    // not resumed like packages, a partial copy of an old version would be garbage
+    let tmp = path.with_extension(format!("tmp.{}", rustix::process::getpid().as_raw_nonzero()));
+    let status = Command::new("curl")
+        .args(["--fail", "--location", "--silent", "--show-error", "--max-time", "30"])
+        .arg("--output")
+        .arg(&tmp)
+        .arg(url)
+        .stdin(Stdio::null())
+        .status()
+        .context("failed to execute curl")?;
+    if !status.success() {
+        let _ = fs::remove_file(&tmp);
+        if age.is_some() {
+            warning!("failed to fetch {url} ({status}), using the copy from before");
+            return Ok(path);
+        }
+        bail!("curl failed to download {url} ({status})");
+    }
+    fs::rename(&tmp, &path).with_context(|| format!("failed to rename {}", tmp.display()))?;
+    Ok(path)
+}
+
+/// Try each server in turn until one of them has `filename`.
+fn fetch_from_servers(
+    servers: &[String],
+    filename: &str,
+    dest: &Path,
+    progress: bool,
+) -> Result<()> {
+    let mut last_err = anyhow!("no servers configured");
+    for server in servers {
+        let url = format!("{}/{filename}", server.trim_end_matches('/'));
+        match curl(&url, dest, progress) {
+            Ok(()) => return Ok(()),
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+/// Verify the detached signature `sig` of `file` against pacman's keyring.
+fn verify_signature(file: &Path, sig: &Path) -> Result<()> {
+    let output = Command::new("gpgv")
+        .args(["--keyring", PACMAN_KEYRING])
+        .arg(sig)
+        .arg(file)
+        .output()
+        .context("failed to execute gpgv")?;
+    if !output.status.success() {
+        bail!("bad signature: {}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Downloads the package files for a set of upgrades into a single directory
+pub struct Downloader {
+    dest: PathBuf,
+    verify_signatures: bool,
+    progress: bool,
+    sizes: SizeFormat,
+}
+
+impl Downloader {
+    /// Create a downloader which stores packages in `dest`, creating it if needed.
+    ///
+    /// Signature verification is skipped with a warning if pacman's keyring isn't readable.
+    pub fn new(dest: impl Into<PathBuf>, progress: bool, sizes: SizeFormat) -> Result<Self> {
+        let dest = dest.into();
+        fs::create_dir_all(&dest)
+            .with_context(|| format!("failed to create cache directory {}", dest.display()))?;
+
+        let verify_signatures = fs::File::open(PACMAN_KEYRING).is_ok();
+        if !verify_signatures {
+            warning!("cannot read {PACMAN_KEYRING}, package signatures won't be checked");
+        }
+        Ok(Self { dest, verify_signatures, progress, sizes })
+    }
+
+    /// Download one package, returning false if it was already downloaded.
+    ///
+    /// The file is first written to `$filename.part` and only renamed once its checksum (and
+    /// signature) are verified, so an interrupted download is resumed by the next run.
+    fn download_one(&self, upgrade: &Upgrade, filename: &str) -> Result<bool> {
+        let path = self.dest.join(filename);
+        let sha256sum = upgrade.sha256sum.as_deref();
+        if cache::find_cached(std::slice::from_ref(&self.dest), filename, sha256sum)?.is_some() {
+            return Ok(false);
+        }
+
+        let repo = upgrade.repo.as_ref().context("package repo is unknown")?;
+        let servers = pacman_conf::repo_servers(repo.as_str());
+        let part = self.dest.join(format!("{filename}.part"));
+
+        // a complete .part file means the last run died between downloading and verifying
+        let have = part.metadata().map(|m| m.len()).unwrap_or(0);
+        if have < upgrade.download_size || upgrade.download_size == 0 {
+            fetch_from_servers(&servers, filename, &part, self.progress)?;
+        }
+
+        if let Some(expected) = sha256sum {
+            let actual = cache::sha256_file(&part)
+                .with_context(|| format!("failed to read {}", part.display()))?;
+            if !actual.eq_ignore_ascii_case(expected) {
+                let _ = fs::remove_file(&part);
+                bail!("SHA256 mismatch (expected {expected}, got {actual})");
+            }
+        }
+
+        if self.verify_signatures {
+            let sig_name = format!("{filename}.sig");
+            let sig = self.dest.join(&sig_name);
+            // signatures are tiny, always fetch them from scratch. Unsigned packages are allowed
+            // by pacman's default "Optional" SigLevel for custom repos, so only warn about them.
+            let _ = fs::remove_file(&sig);
+            if fetch_from_servers(&servers, &sig_name, &sig, false).is_err() {
+                warning!("no signature found for {filename}");
+            } else if let Err(err) = verify_signature(&part, &sig) {
+                let _ = fs::remove_file(&part);
+                let _ = fs::remove_file(&sig);
+                return Err(err);
+            }
+        }
+
+        fs::rename(&part, &path).with_context(|| {
+            format!("failed to rename {} to {}", part.display(), path.display())
+        })?;
+        Ok(true)
+    }
+
+    /// Download every upgrade that isn't already in the destination directory, reporting progress
+    /// to `out`. Failures are reported and the remaining packages are still downloaded, the
+    /// returned `Err` only summarizes how many failed.
+    pub fn download_all(&self, out: &mut impl Write, upgrades: &[Upgrade]) -> Result<()> {
+        let total = upgrades.len();
+        let mut downloaded = 0;
+        let mut downloaded_bytes = 0;
+        let mut failed = 0;
+
+        for (i, upgrade) in upgrades.iter().enumerate() {
+            let filename = match &upgrade.filename {
+                Some(filename) => filename,
+                None => {
+                    warning!("no package file known for {}, skipping", upgrade.pkgname);
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            writeln!(
+                out,
+                "({n:>w$}/{total}) {filename} ({size})",
+                n = i + 1,
+                w = total.to_string().len(),
+                size = self.sizes.format(upgrade.download_size),
+            )?;
+            out.flush()?;
+
+            match self.download_one(upgrade, filename) {
+                Ok(true) => {
+                    downloaded += 1;
+                    downloaded_bytes += upgrade.download_size;
+                }
+                Ok(false) => writeln!(out, "  already downloaded")?,
+                Err(err) => {
+                    warning!("failed to download {filename}: {err:#}");
+                    failed += 1;
+                }
+            }
+        }
+
+        writeln!(
+            out,
+            "Downloaded {downloaded} packages ({}) into {}",
+            self.sizes.format(downloaded_bytes),
+            self.dest.display()
+        )?;
+        if failed > 0 {
+            bail!("{failed} packages failed to download");
+        }
+        Ok(())
+    }
+}
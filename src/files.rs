@@ -0,0 +1,215 @@
+//! Compare the file lists of upgradable packages with what's currently installed
+//!
+//! The new file lists come from the sync `.files` databases, and the old ones from the local
+//! database. Along with what each upgrade adds and removes, this finds files that pacman would
+//! refuse to install because they're owned by another package or exist untracked on disk.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use ahash::{HashMap, HashSet};
+use owo_colors::OwoColorize;
+
+use crate::theme;
+
+/// A file added by an upgrade which pacman will report as a conflict
+#[derive(Debug)]
+pub struct Conflict {
+    pub path: String,
+    /// The installed package which owns the file, or None if it exists in the filesystem but
+    /// isn't owned by any package.
+    pub owner: Option<String>,
+}
+
+/// Files added and removed by upgrading one package
+#[derive(Debug)]
+pub struct FileChanges {
+    pub pkgname: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub conflicts: Vec<Conflict>,
+}
+
+impl FileChanges {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+/// Compute the file changes for each of `pkgnames`.
+///
+/// `new_files` are the file lists from the sync `.files` databases, and `installed` the file lists
+/// of all locally installed packages. Filesystem paths are resolved relative to `root`. Packages
+/// that aren't in `new_files` are skipped, and the result is in the same order as `pkgnames`.
+///
+/// A file that moves between two packages which are both being upgraded isn't a conflict, and
+/// neither are directories.
+pub fn compare(
+    pkgnames: &[&str],
+    new_files: &HashMap<String, Vec<String>>,
+    installed: &HashMap<String, Vec<String>>,
+    root: impl AsRef<Path>,
+) -> Vec<FileChanges> {
+    let root = root.as_ref();
+    let owners: HashMap<&str, &str> = installed
+        .iter()
+        .flat_map(|(pkgname, files)| files.iter().map(move |f| (&**f, &**pkgname)))
+        .collect();
+    let no_files = Vec::new();
+
+    pkgnames
+        .iter()
+        .filter_map(|&pkgname| {
+            let new = new_files.get(pkgname)?;
+            let old = installed.get(pkgname).unwrap_or(&no_files);
+            let new_set: HashSet<&str> = new.iter().map(String::as_str).collect();
+            let old_set: HashSet<&str> = old.iter().map(String::as_str).collect();
+
+            let mut added: Vec<String> =
+                new.iter().filter(|f| !old_set.contains(&***f)).cloned().collect();
+            let mut removed: Vec<String> =
+                old.iter().filter(|f| !new_set.contains(&***f)).cloned().collect();
+            added.sort_unstable();
+            removed.sort_unstable();
+
+            let conflicts = added
+                .iter()
+                // directories, listed with a trailing slash, can be shared by any packages
+                .filter(|path| !path.ends_with('/'))
+                .filter_map(|path| match owners.get(&**path) {
+                    Some(&owner) => {
+                        // fine if the owner is also upgraded to a version without this file
+                        let moved = pkgnames.contains(&owner)
+                            && new_files.get(owner).is_some_and(|f| !f.contains(path));
+                        (!moved).then(|| Conflict { path: path.clone(), owner: Some(owner.into()) })
+                    }
+                    None => root
+                        .join(path)
+                        .symlink_metadata()
+                        .is_ok()
+                        .then(|| Conflict { path: path.clone(), owner: None }),
+                })
+                .collect();
+
+            Some(FileChanges { pkgname: pkgname.to_owned(), added, removed, conflicts })
+        })
+        .collect()
+}
+
+/// Write the added, removed, and conflicting files for each package that has any.
+pub fn write_changes(out: &mut impl Write, changes: &[FileChanges]) -> io::Result<()> {
+    let changed: Vec<&FileChanges> = changes.iter().filter(|c| !c.is_empty()).collect();
+    if changed.is_empty() {
+        return writeln!(out, "No files are added or removed by the upgrades.");
+    }
+
+    let theme = theme::get();
+    writeln!(out, "File changes:")?;
+    for c in changed {
+        writeln!(out, "{}", c.pkgname.style(theme.header))?;
+        for path in c.added.iter() {
+            writeln!(out, "  {} {path}", "+".style(theme.new))?;
+        }
+        for path in c.removed.iter() {
+            writeln!(out, "  {} {path}", "-".style(theme.old))?;
+        }
+        for conflict in c.conflicts.iter() {
+            match &conflict.owner {
+                Some(owner) => writeln!(
+                    out,
+                    "  {} {} (owned by {owner})",
+                    "!".style(theme.warning),
+                    conflict.path
+                )?,
+                None => writeln!(
+                    out,
+                    "  {} {} (exists in filesystem)",
+                    "!".style(theme.warning),
+                    conflict.path
+                )?,
+            }
+        }
+    }
+
+    let total_conflicts: usize = changes.iter().map(|c| c.conflicts.len()).sum();
+    if total_conflicts > 0 {
+        writeln!(out, "File conflicts:       {total_conflicts:5}")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn lists(lists: &[(&str, &[&str])]) -> HashMap<String, Vec<String>> {
+        lists
+            .iter()
+            .map(|(pkgname, files)| {
+                (pkgname.to_string(), files.iter().map(|f| f.to_string()).collect())
+            })
+            .collect()
+    }
+
+    fn conflicts(changes: &FileChanges) -> Vec<(&str, Option<&str>)> {
+        changes.conflicts.iter().map(|c| (&*c.path, c.owner.as_deref())).collect()
+    }
+
+    #[test]
+    fn file_moves_between_upgrades() {
+        let root = tempfile::tempdir().unwrap();
+        let installed = lists(&[("foo", &["usr/bin/foo", "usr/bin/tool"]), ("foo-tools", &[])]);
+        let new = lists(&[("foo", &["usr/bin/foo"]), ("foo-tools", &["usr/bin/tool"])]);
+        let [foo, tools] = &compare(&["foo", "foo-tools"], &new, &installed, root.path())[..]
+        else {
+            panic!()
+        };
+        assert!(foo.added.is_empty());
+        assert_eq!(foo.removed, ["usr/bin/tool"]);
+        assert_eq!(tools.added, ["usr/bin/tool"]);
+        assert!(conflicts(tools).is_empty());
+
+        // without foo's upgrade, foo still owns it
+        let [tools] = &compare(&["foo-tools"], &new, &installed, root.path())[..] else { panic!() };
+        assert_eq!(conflicts(tools), [("usr/bin/tool", Some("foo"))]);
+    }
+
+    #[test]
+    fn conflicts_with_installed_and_untracked_files() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/lib")).unwrap();
+        fs::write(root.path().join("usr/lib/libbar.so"), "").unwrap();
+        let installed = lists(&[("bar", &["usr/bin/bar"]), ("baz", &["usr/share/baz.conf"])]);
+        let new = lists(&[(
+            "bar",
+            &["usr/bin/bar", "usr/lib/libbar.so", "usr/share/baz.conf", "usr/share/bar.conf"],
+        )]);
+        let [bar] = &compare(&["bar", "missing"], &new, &installed, root.path())[..] else {
+            panic!()
+        };
+        assert_eq!(bar.added, ["usr/lib/libbar.so", "usr/share/bar.conf", "usr/share/baz.conf"]);
+        assert_eq!(
+            conflicts(bar),
+            [("usr/lib/libbar.so", None), ("usr/share/baz.conf", Some("baz"))]
+        );
+    }
+
+    #[test]
+    fn shared_directories() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("usr/share/icons/hicolor")).unwrap();
+        let installed = lists(&[
+            ("hicolor-icon-theme", &["usr/", "usr/share/", "usr/share/icons/hicolor/"]),
+            ("qux", &["usr/", "usr/bin/", "usr/bin/qux"]),
+        ]);
+        let new = lists(&[(
+            "qux",
+            &["usr/", "usr/bin/", "usr/bin/qux", "usr/share/", "usr/share/icons/hicolor/"],
+        )]);
+        let [qux] = &compare(&["qux"], &new, &installed, root.path())[..] else { panic!() };
+        assert_eq!(qux.added, ["usr/share/", "usr/share/icons/hicolor/"]);
+        assert!(conflicts(qux).is_empty());
+    }
+}
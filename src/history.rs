@@ -0,0 +1,289 @@
+//! History of the upgrades seen by previous runs
+//!
+//! Each run against the sync databases appends its set of upgrades to
+//! `$XDG_DATA_HOME/checkupgrades/history.jsonl`, one JSON object per line. Runs reading the
+//! upgrades from a file or stdin only compare against it. Comparing against the previous runs
+//! tells which upgrades are new, how long each one has been pending, and which ones went away
+//! since the last run.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ahash::{HashMap, HashSet};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{Repo, Upgrade};
+
+/// How many runs are kept in the history file
+const MAX_RUNS: usize = 200;
+
+/// The default location of the history file
+pub fn default_path() -> PathBuf {
+    crate::xdg_dir("XDG_DATA_HOME", ".local/share").join("checkupgrades/history.jsonl")
+}
+
+/// One upgrade as recorded in the history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub pkgname: String,
+    pub repo: Option<String>,
+    pub oldver: String,
+    pub newver: String,
+}
+
+impl Entry {
+    fn of(u: &Upgrade) -> Self {
+        Self {
+            pkgname: u.pkgname.clone(),
+            repo: u.repo.as_ref().map(|r| r.as_str().to_owned()),
+            oldver: u.oldver.clone(),
+            newver: u.newver.clone(),
+        }
+    }
+
+    pub fn repo(&self) -> Repo {
+        self.repo.as_deref().map_or(Repo::Unknown, Repo::from)
+    }
+}
+
+/// The upgrades seen by one run, at `time` seconds since the Unix epoch
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Run {
+    pub time: u64,
+    pub upgrades: Vec<Entry>,
+}
+
+impl Run {
+    fn contains(&self, pkgname: &str, newver: &str) -> bool {
+        self.upgrades.iter().any(|e| e.pkgname == pkgname && e.newver == newver)
+    }
+}
+
+/// Why an upgrade from the previous run isn't pending any more
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reason {
+    /// The new version is installed
+    Installed,
+    /// The package isn't installed any more
+    Removed,
+    /// The new version isn't offered by the repos any more, or the upgrade was done some other way
+    Withdrawn,
+}
+
+impl Reason {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Installed => "installed",
+            Self::Removed => "removed",
+            Self::Withdrawn => "withdrawn",
+        }
+    }
+}
+
+/// An upgrade from the previous run which is no longer pending
+#[derive(Debug, Serialize)]
+pub struct Disappeared {
+    #[serde(flatten)]
+    pub entry: Entry,
+    pub reason: Reason,
+}
+
+/// The runs in the history file, oldest first
+#[derive(Debug, Default)]
+pub struct History {
+    runs: Vec<Run>,
+}
+
+impl History {
+    /// Load the history from `path`. A missing file is an empty history, and lines which can't be
+    /// parsed are skipped, so a truncated write only loses that run.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()))
+            }
+        };
+        let runs = contents.lines().filter_map(|line| serde_json::from_str(line).ok()).collect();
+        Ok(Self { runs })
+    }
+
+    /// The most recent run
+    pub fn previous(&self) -> Option<&Run> {
+        self.runs.last()
+    }
+
+    /// When the upgrade of `pkgname` to `newver` was first seen, if it's been pending in every run
+    /// since then.
+    fn first_seen(&self, pkgname: &str, newver: &str) -> Option<u64> {
+        self.runs
+            .iter()
+            .rev()
+            .take_while(|run| run.contains(pkgname, newver))
+            .last()
+            .map(|run| run.time)
+    }
+
+    /// Set `first_seen` and `new` on each upgrade, as of a run at time `now`. Upgrades are only
+    /// marked as new when there is a previous run to compare with.
+    pub fn annotate(&self, upgrades: &mut [Upgrade], now: u64) {
+        let previous = self.previous();
+        for u in upgrades.iter_mut() {
+            u.first_seen = Some(self.first_seen(&u.pkgname, &u.newver).unwrap_or(now));
+            u.new = previous.is_some_and(|run| !run.contains(&u.pkgname, &u.newver));
+        }
+    }
+
+    /// The upgrades from the previous run whose packages have no pending upgrade now.
+    /// `installed` maps pkgnames to their installed versions.
+    pub fn disappeared(
+        &self,
+        upgrades: &[Upgrade],
+        installed: &HashMap<String, String>,
+    ) -> Vec<Disappeared> {
+        let Some(previous) = self.previous() else {
+            return Vec::new();
+        };
+        let pending: HashSet<&str> = upgrades.iter().map(|u| &*u.pkgname).collect();
+        previous
+            .upgrades
+            .iter()
+            .filter(|e| !pending.contains(&*e.pkgname))
+            .map(|e| {
+                let reason = match installed.get(&e.pkgname) {
+                    None => Reason::Removed,
+                    Some(version) if *version == e.newver => Reason::Installed,
+                    Some(_) => Reason::Withdrawn,
+                };
+                Disappeared { entry: e.clone(), reason }
+            })
+            .collect()
+    }
+
+    /// Add a run with `upgrades` at time `now`, dropping the oldest runs beyond `MAX_RUNS`
+    pub fn record(&mut self, upgrades: &[Upgrade], now: u64) {
+        self.runs.push(Run { time: now, upgrades: upgrades.iter().map(Entry::of).collect() });
+        let excess = self.runs.len().saturating_sub(MAX_RUNS);
+        self.runs.drain(..excess);
+    }
+
+    /// Write the history to `path`, replacing it atomically
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut contents = String::new();
+        for run in self.runs.iter() {
+            contents += &serde_json::to_string(run)?;
+            contents.push('\n');
+        }
+        crate::write_atomic(path, contents.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrades(lines: &[&str]) -> Vec<Upgrade> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn runs(runs: &[(u64, &[&str])]) -> History {
+        let mut history = History::default();
+        for (time, lines) in runs {
+            history.record(&upgrades(lines), *time);
+        }
+        history
+    }
+
+    fn annotated(history: &History, lines: &[&str], now: u64) -> Vec<(Option<u64>, bool)> {
+        let mut upgrades = upgrades(lines);
+        history.annotate(&mut upgrades, now);
+        upgrades.iter().map(|u| (u.first_seen, u.new)).collect()
+    }
+
+    #[test]
+    fn empty_history() {
+        let history = History::default();
+        // nothing to compare with, so nothing is new
+        assert_eq!(annotated(&history, &["linux 6.7.3-1 -> 6.7.4-1"], 300), [(Some(300), false)]);
+        assert!(history.disappeared(&[], &HashMap::default()).is_empty());
+    }
+
+    #[test]
+    fn same_version_across_runs() {
+        let history = runs(&[
+            (100, &["linux 6.7.3-1 -> 6.7.4-1"]),
+            (200, &["linux 6.7.3-1 -> 6.7.4-1", "glibc 2.39-1 -> 2.39-2"]),
+        ]);
+        let pending = ["linux 6.7.3-1 -> 6.7.4-1", "glibc 2.39-1 -> 2.39-2", "gcc 13-1 -> 14-1"];
+        assert_eq!(
+            annotated(&history, &pending, 300),
+            [(Some(100), false), (Some(200), false), (Some(300), true)]
+        );
+    }
+
+    #[test]
+    fn bumped_version() {
+        let history =
+            runs(&[(100, &["linux 6.7.3-1 -> 6.7.4-1"]), (200, &["linux 6.7.3-1 -> 6.7.5-1"])]);
+        assert_eq!(annotated(&history, &["linux 6.7.3-1 -> 6.7.5-1"], 300), [(Some(200), false)]);
+        assert_eq!(annotated(&history, &["linux 6.7.3-1 -> 6.7.6-1"], 300), [(Some(300), true)]);
+        // only counted from when it has been pending without a break
+        let mut history = runs(&[(100, &["linux 6.7.3-1 -> 6.7.4-1"])]);
+        history.record(&[], 200);
+        history.record(&upgrades(&["linux 6.7.3-1 -> 6.7.4-1"]), 300);
+        assert_eq!(annotated(&history, &["linux 6.7.3-1 -> 6.7.4-1"], 400), [(Some(300), false)]);
+    }
+
+    #[test]
+    fn disappeared_upgrades() {
+        let history = runs(&[(
+            100,
+            &[
+                "linux 6.7.3-1 -> 6.7.4-1",
+                "firefox 122.0-1 -> 122.0.1-1",
+                "glibc 2.39-1 -> 2.39-2",
+                "mesa 1:24.0.0-1 -> 1:24.0.1-1",
+            ],
+        )]);
+        let installed: HashMap<String, String> = [("firefox", "122.0.1-1"), ("mesa", "1:24.0.0-1")]
+            .into_iter()
+            .map(|(pkgname, version)| (pkgname.to_owned(), version.to_owned()))
+            .collect();
+        let pending = upgrades(&["linux 6.7.3-1 -> 6.7.5-1"]);
+        let disappeared = history.disappeared(&pending, &installed);
+        let disappeared: Vec<(&str, Reason)> =
+            disappeared.iter().map(|d| (&*d.entry.pkgname, d.reason)).collect();
+        assert_eq!(
+            disappeared,
+            [
+                ("firefox", Reason::Installed),
+                ("glibc", Reason::Removed),
+                ("mesa", Reason::Withdrawn),
+            ]
+        );
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        assert!(History::load(&path).unwrap().previous().is_none());
+        let mut history = History::default();
+        for time in 0..MAX_RUNS as u64 + 5 {
+            history.record(&upgrades(&["linux 6.7.3-1 -> 6.7.4-1"]), time);
+        }
+        history.save(&path).unwrap();
+        // a truncated last line only loses that run
+        let mut contents = fs::read_to_string(&path).unwrap();
+        contents.push_str("{\"time\": 1000, \"upgr");
+        fs::write(&path, contents).unwrap();
+        let history = History::load(&path).unwrap();
+        assert_eq!(history.runs.len(), MAX_RUNS);
+        assert_eq!(history.runs[0].time, 5);
+        assert_eq!(history.previous().unwrap().time, MAX_RUNS as u64 + 4);
+    }
+}
@@ -0,0 +1,134 @@
+//! User commands run when there are upgrades, to send notifications or messages
+//!
+//! Hooks are given as `--hook EVENT=COMMAND` or in the `[hooks]` table of the config file, and are
+//! run with `sh -c` when their event happens in a run against the sync databases, not when the
+//! upgrades are read from a file or stdin. The upgrades which triggered the hook are written to
+//! its stdin as JSON, like `{"event": "new", "upgrades": [...]}`, and the environment has
+//! `CHECKUPGRADES_EVENT`, plus the numbers for all pending upgrades: `CHECKUPGRADES_COUNT`,
+//! `CHECKUPGRADES_NEW`, `CHECKUPGRADES_SECURITY`, and the sizes in bytes
+//! `CHECKUPGRADES_DOWNLOAD_SIZE`, `CHECKUPGRADES_INSTALL_SIZE` and `CHECKUPGRADES_NET_SIZE`.
+
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::builder::PossibleValue;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{warning, Totals, Upgrade};
+
+/// When a hook runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Available,
+    New,
+    Security,
+}
+
+impl ValueEnum for Event {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Available, Self::New, Self::Security]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Available => PossibleValue::new("available").help("There are upgrades"),
+            Self::New => PossibleValue::new("new")
+                .help("There are upgrades which weren't there in the previous run"),
+            Self::Security => PossibleValue::new("security")
+                .help("There are upgrades which fix security issues, with --security"),
+        })
+    }
+}
+
+impl Event {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Available => "available",
+            Self::New => "new",
+            Self::Security => "security",
+        }
+    }
+
+    /// The upgrades which trigger this event
+    fn upgrades(self, upgrades: &[Upgrade]) -> Vec<&Upgrade> {
+        match self {
+            Self::Available => upgrades.iter().collect(),
+            Self::New => upgrades.iter().filter(|u| u.new).collect(),
+            Self::Security => upgrades.iter().filter(|u| !u.security.is_empty()).collect(),
+        }
+    }
+}
+
+/// A command to run on an event
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub event: Event,
+    pub command: String,
+}
+
+/// Parse a hook like `new=notify-send "New upgrades"`
+pub fn parse(s: &str) -> Result<Hook> {
+    let (event, command) =
+        s.split_once('=').ok_or_else(|| anyhow!("expected EVENT=COMMAND, got {s:?}"))?;
+    let event = Event::from_str(event, false).map_err(|_| {
+        let events: Vec<&str> = Event::value_variants().iter().map(|e| e.as_str()).collect();
+        anyhow!("unknown hook event {event:?}, expected one of {}", events.join(", "))
+    })?;
+    Ok(Hook { event, command: command.to_owned() })
+}
+
+/// What a hook gets on stdin
+#[derive(Serialize)]
+struct HookInput<'a> {
+    event: &'static str,
+    upgrades: &'a [&'a Upgrade],
+}
+
+/// Run the hooks whose events are triggered by `upgrades`. Failing hooks are warned about.
+pub fn run_all(hooks: &[Hook], upgrades: &[Upgrade]) {
+    let totals = Totals::of(upgrades);
+    let new = upgrades.iter().filter(|u| u.new).count();
+    for hook in hooks {
+        let triggered = hook.event.upgrades(upgrades);
+        if triggered.is_empty() {
+            continue;
+        }
+        if let Err(err) = run(hook, &triggered, &totals, new) {
+            warning!("{} hook {:?} failed: {err:#}", hook.event.as_str(), hook.command);
+        }
+    }
+}
+
+/// Run one hook and wait for it
+fn run(hook: &Hook, upgrades: &[&Upgrade], totals: &Totals, new: usize) -> Result<()> {
+    let input = serde_json::to_vec(&HookInput { event: hook.event.as_str(), upgrades })?;
+    // the hook's output goes to stderr so it can't mix with ours
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(&hook.command)
+        .stdin(Stdio::piped())
+        .stdout(io::stderr())
+        .env("CHECKUPGRADES_EVENT", hook.event.as_str())
+        .env("CHECKUPGRADES_COUNT", totals.packages.to_string())
+        .env("CHECKUPGRADES_NEW", new.to_string())
+        .env("CHECKUPGRADES_SECURITY", totals.security.to_string())
+        .env("CHECKUPGRADES_DOWNLOAD_SIZE", totals.download_size.to_string())
+        .env("CHECKUPGRADES_INSTALL_SIZE", totals.install_size.to_string())
+        .env("CHECKUPGRADES_NET_SIZE", totals.net_size.to_string())
+        .spawn()
+        .context("failed to start it")?;
+    let mut stdin = child.stdin.take().unwrap();
+    match stdin.write_all(&input) {
+        // hooks don't have to read their input
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result.context("failed to write its input")?,
+    }
+    drop(stdin);
+    let status = child.wait()?;
+    if !status.success() {
+        bail!("it exited with {status}");
+    }
+    Ok(())
+}
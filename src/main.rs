@@ -1,9 +1,8 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::ffi::{OsStr, OsString};
 use std::fmt;
 use std::fs;
-use std::io::{self, Write};
-use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::io::{self, IsTerminal, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::str::FromStr;
@@ -12,11 +11,48 @@ use std::sync::OnceLock;
 use ahash::HashSet;
 use anstream::{AutoStream, ColorChoice};
 use anyhow::{anyhow, Context, Result};
-use bstr::ByteSlice;
+use clap::builder::PossibleValue;
 use clap::{Arg, ArgAction};
-use owo_colors::{AnsiColors, OwoColorize};
+use serde::de::DeserializeOwned;
+use serde::{Serialize, Serializer};
+
+use version::VersionClass;
 
 mod alpm;
+mod cache;
+mod config;
+mod cooldown;
+mod daemon;
+mod diskspace;
+mod download;
+mod files;
+mod history;
+mod hooks;
+mod metrics;
+mod news;
+mod output;
+mod pacman_conf;
+mod pacman_log;
+mod pager;
+mod restart;
+mod security;
+mod size;
+mod sort;
+mod statusbar;
+mod theme;
+mod version;
+
+/// Print a warning to stderr, with the `Warning:` prefix styled by the theme
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        anstream::eprintln!(
+            "{} {}",
+            owo_colors::OwoColorize::style(&"Warning:", $crate::theme::get().warning),
+            format_args!($($arg)*),
+        )
+    };
+}
 
 #[macro_export]
 macro_rules! regex {
@@ -26,15 +62,36 @@ macro_rules! regex {
     }};
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize)]
 struct Upgrade {
     repo: Option<Repo>,
     pkgname: String,
+    /// The pkgbase for split packages, from the sync DB
+    pkgbase: Option<String>,
     oldver: String,
     newver: String,
+    class: VersionClass,
     download_size: u64,
     install_size: u64,
     old_size: u64,
+    /// Package file name and checksum from the sync DB
+    filename: Option<String>,
+    sha256sum: Option<String>,
+    /// Whether the package file is already in a pacman cache directory
+    cached: bool,
+    /// Build date of the new version, in seconds since the Unix epoch
+    build_date: Option<u64>,
+    /// When the new version is old enough to install according to the cooldown policy
+    ready_at: Option<u64>,
+    /// When this upgrade was first seen by a run, from the history
+    first_seen: Option<u64>,
+    /// Whether this upgrade wasn't pending in the previous run
+    new: bool,
+    /// When the package was last installed or upgraded, from pacman's log
+    last_upgrade: Option<u64>,
+    /// The security issues fixed by this upgrade, most severe first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    security: Vec<security::Fix>,
 }
 
 impl FromStr for Upgrade {
@@ -42,33 +99,47 @@ impl FromStr for Upgrade {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let re = regex!(r"^(\S+) (\S+) -> (\S+)$");
         let caps = re.captures(s).ok_or(())?;
+        let oldver = caps.get(2).unwrap().as_str();
+        let newver = caps.get(3).unwrap().as_str();
         Ok(Self {
             repo: None,
             pkgname: caps.get(1).unwrap().as_str().into(),
-            oldver: caps.get(2).unwrap().as_str().into(),
-            newver: caps.get(3).unwrap().as_str().into(),
+            pkgbase: None,
+            oldver: oldver.into(),
+            newver: newver.into(),
+            class: version::classify(oldver, newver),
             download_size: 0,
             install_size: 0,
             old_size: 0,
+            filename: None,
+            sha256sum: None,
+            cached: false,
+            build_date: None,
+            ready_at: None,
+            first_seen: None,
+            new: false,
+            last_upgrade: None,
+            security: Vec::new(),
         })
     }
 }
 
 impl Upgrade {
-    fn common_length(&self) -> usize {
-        let old = &self.oldver;
-        let new = &self.newver;
-        // not ready to handle multibyte unicode characters
-        assert!(old.is_ascii());
-        assert!(new.is_ascii());
+    /// Whether the upgrade is past its cooldown at time `now`. Upgrades with an unknown build date
+    /// are always ready.
+    fn is_ready(&self, now: u64) -> bool {
+        self.ready_at.is_none_or(|ready_at| ready_at <= now)
+    }
 
-        // get the length of the common prefix
-        let common = old.bytes().zip(new.bytes()).take_while(|(ob, nb)| ob == nb).count();
+    /// The repo, or `Repo::Unknown` if it couldn't be determined
+    fn repo_or_unknown(&self) -> &Repo {
+        static UNKNOWN: Repo = Repo::Unknown;
+        self.repo.as_ref().unwrap_or(&UNKNOWN)
+    }
 
-        // walk backwards to the first non-alphanumeric character
-        let extra = old[..common].bytes().rev().take_while(|c| c.is_ascii_alphanumeric()).count();
-        assert!(extra <= common);
-        common - extra
+    /// Change in installed size, in bytes
+    fn net_size(&self) -> i64 {
+        (self.install_size as i64) - (self.old_size as i64)
     }
 }
 
@@ -77,13 +148,14 @@ impl Upgrade {
 /// The automatically derived (Partial)Ord implementation does what we want - sorts first by the
 /// enum discriminant value (order variants are defined) and lexographically if both are
 /// Repo::Custom variants.
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum Repo {
     Core,
     Extra,
     Community,
     Multilib,
     Custom(String),
+    #[default]
     Unknown,
 }
 
@@ -124,9 +196,9 @@ impl fmt::Display for Repo {
     }
 }
 
-impl Default for Repo {
-    fn default() -> Self {
-        Self::Unknown
+impl Serialize for Repo {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
     }
 }
 
@@ -141,45 +213,117 @@ impl Repo {
             Self::Unknown => "[unknown]",
         }
     }
+}
 
-    fn get_color(&self) -> AnsiColors {
-        match self {
-            Self::Core => AnsiColors::Magenta,
-            Self::Extra => AnsiColors::Blue,
-            Self::Community => AnsiColors::Red,
-            Self::Multilib => AnsiColors::Green,
-            Self::Custom(_) => AnsiColors::Cyan,
-            Self::Unknown => AnsiColors::White,
+/// Set from `--db-path`, `$CHECKUPDATES_DB`, or the config file when parsing args
+static CHECKUPDATES_DB: OnceLock<PathBuf> = OnceLock::new();
+
+fn checkupdates_db_path() -> &'static Path {
+    CHECKUPDATES_DB.get_or_init(|| {
+        let uid = rustix::process::getuid().as_raw();
+        let mut path =
+            env::var_os("TMPDIR").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("/tmp"));
+        path.push(format!("checkup-db-{uid}"));
+        path
+    })
+}
+
+/// Match `s` against a glob where `*` matches any run of characters and `?` any one character
+fn glob_match(glob: &str, s: &str) -> bool {
+    let glob: Vec<char> = glob.chars().collect();
+    let s: Vec<char> = s.chars().collect();
+    // the position after the last `*`, and where in `s` it's currently matched up to
+    let mut star: Option<(usize, usize)> = None;
+    let (mut g, mut i) = (0, 0);
+    while i < s.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g + 1, i));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == s[i] => {
+                g += 1;
+                i += 1;
+            }
+            // backtrack, letting the last `*` match one more character
+            _ => match star {
+                Some((after, matched)) => {
+                    g = after;
+                    i = matched + 1;
+                    star = Some((after, matched + 1));
+                }
+                None => return false,
+            },
         }
     }
+    glob[g..].iter().all(|&c| c == '*')
 }
 
-fn checkupdates_db_path() -> &'static Path {
-    static CELL: OnceLock<PathBuf> = OnceLock::new();
-    CELL.get_or_init(|| {
-        env::var_os("CHECKUPDATES_DB").map(PathBuf::from).unwrap_or_else(|| {
-            let uid = rustix::process::getuid().as_raw();
-            let mut path =
-                env::var_os("TMPDIR").map(PathBuf::from).unwrap_or_else(|| PathBuf::from("/tmp"));
-            path.push(format!("checkup-db-{uid}"));
-            path
-        })
-    })
+/// Get an XDG base directory from the environment variable `var`, falling back to `fallback`
+/// relative to `$HOME` when it's unset or not absolute.
+fn xdg_dir(var: &str, fallback: &str) -> PathBuf {
+    match env::var_os(var).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => dir,
+        _ => env::var_os("HOME").map(PathBuf::from).unwrap_or_default().join(fallback),
+    }
+}
+
+/// The default directory for `--download`, `$XDG_CACHE_HOME/checkupgrades/pkg`
+fn default_download_dir() -> PathBuf {
+    xdg_dir("XDG_CACHE_HOME", ".cache").join("checkupgrades/pkg")
+}
+
+/// Call pacman to sync the checkupdates DB, `op` is `-Sy` or `-Fy`. This needs to be done with
+/// fakeroot or pacman will immediately error out
+fn fakeroot_pacman_sync(checkupdates_db: &Path, op: &str) -> Result<()> {
+    let mut sync_cmd = Command::new("fakeroot");
+    sync_cmd
+        .args(["--", "pacman", op, "--disable-sandbox", "--dbpath"])
+        .arg(checkupdates_db)
+        .args(["--logfile", "/dev/null"]);
+    let sync_output =
+        sync_cmd.output().with_context(|| format!("failed to execute (fakeroot) pacman {op}"))?;
+
+    if !sync_output.status.success() {
+        eprintln!("Failed to sync databases!");
+        eprintln!("Command: {sync_cmd:?}");
+        eprintln!("Standard Output:");
+        let _ = io::stderr().write_all(&sync_output.stdout);
+        eprintln!("Standard Error:");
+        let _ = io::stderr().write_all(&sync_output.stderr);
+        return Err(anyhow!("pacman {op} failed"));
+    }
+    Ok(())
+}
+
+/// Write `contents` to `path` through a temporary file, so readers never see it half written
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create directory {}", dir.display()))?;
+    }
+    let tmp = path.with_extension(format!("tmp.{}", rustix::process::getpid().as_raw_nonzero()));
+    fs::write(&tmp, contents).with_context(|| format!("failed to write {}", tmp.display()))?;
+    fs::rename(&tmp, path).with_context(|| format!("failed to rename {}", tmp.display()))
+}
+
+/// Load the JSON state file at `path`, a missing file means the default state. `what` names the
+/// contents in the error for an invalid file.
+fn load_state<T: DeserializeOwned + Default>(path: &Path, what: &str) -> Result<T> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .with_context(|| format!("invalid {what} in {}", path.display())),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(err) => Err(err).with_context(|| format!("failed to read {}", path.display())),
+    }
 }
 
 /// This is nominally a reimplementation of /usr/bin/checkupdates, but with nicer error handling
-fn get_all_upgrades() -> Result<Vec<Upgrade>> {
-    // figure out the main pacman DB path. Normally this should just be "/var/lib/pacman/" but
-    // check pacman-conf in case it's set to something different somehow
-    let dbpath = match Command::new("pacman-conf").arg("DBPath").output() {
-        Ok(output) if output.status.success() => output.stdout.trim().to_owned(),
-        _ => Vec::new(),
-    };
-    let dbpath = if dbpath.is_empty() || !Path::new(OsStr::from_bytes(&dbpath)).is_dir() {
-        PathBuf::from("/var/lib/pacman/")
-    } else {
-        PathBuf::from(OsString::from_vec(dbpath))
-    };
+///
+/// When `sync_files` is set, the `.files` databases are synced too. Without `sync`, the copy of the
+/// sync databases from the last run is used as is, which is quick and works offline.
+fn get_all_upgrades(sync: bool, sync_files: bool) -> Result<Vec<Upgrade>> {
+    let dbpath = pacman_conf::db_path();
 
     // get the checkup db path
     let checkupdates_db = checkupdates_db_path();
@@ -203,23 +347,19 @@ fn get_all_upgrades() -> Result<Vec<Upgrade>> {
         }
     }
 
-    // Call pacman to sync the checkupdates DB. This needs to be done with fakeroot or pacman will
-    // immediately error out
-    let mut sync_cmd = Command::new("fakeroot");
-    sync_cmd
-        .args(["--", "pacman", "-Sy", "--disable-sandbox", "--dbpath"])
-        .arg(checkupdates_db)
-        .args(["--logfile", "/dev/null"]);
-    let sync_output = sync_cmd.output().context("failed to execute (fakeroot) pacman -Sy")?;
-
-    if !sync_output.status.success() {
-        eprintln!("Failed to fetch updates!");
-        eprintln!("Command: {sync_cmd:?}");
-        eprintln!("Standard Output:");
-        let _ = io::stderr().write_all(&sync_output.stdout);
-        eprintln!("Standard Error:");
-        let _ = io::stderr().write_all(&sync_output.stderr);
-        return Err(anyhow!("cannot fetch updates"));
+    // Sync the package databases, and the file lists if we'll need them
+    if sync {
+        let synced = fakeroot_pacman_sync(checkupdates_db, "-Sy");
+        metrics::SyncStatus::record(synced.is_ok(), cooldown::now());
+        synced.context("cannot fetch updates")?;
+        if sync_files {
+            fakeroot_pacman_sync(checkupdates_db, "-Fy").context("cannot fetch file lists")?;
+        }
+    } else if !checkupdates_db.join("sync").is_dir() {
+        return Err(anyhow!(
+            "no copy of the sync databases in {} yet, run without --no-sync first",
+            checkupdates_db.display()
+        ));
     }
 
     // Call pacman to list available updates. This doesn't need fakeroot
@@ -249,6 +389,26 @@ fn get_all_upgrades() -> Result<Vec<Upgrade>> {
         .collect())
 }
 
+/// Mark upgrades whose package files are already in one of pacman's cache directories.
+///
+/// `extra_dir` (our own download directory) is checked after pacman's. With `verify`, cached files
+/// are only counted if their SHA256 checksum matches the sync DB.
+fn check_cached(upgrades: &mut [Upgrade], extra_dir: &Path, verify: bool) {
+    let mut cache_dirs = pacman_conf::cache_dirs();
+    cache_dirs.push(extra_dir.to_owned());
+    for upgrade in upgrades.iter_mut() {
+        let filename = match &upgrade.filename {
+            Some(filename) => filename,
+            None => continue,
+        };
+        let sha256sum = if verify { upgrade.sha256sum.as_deref() } else { None };
+        match cache::find_cached(&cache_dirs, filename, sha256sum) {
+            Ok(path) => upgrade.cached = path.is_some(),
+            Err(err) => warning!("failed to check cache for {filename}: {err:#}"),
+        }
+    }
+}
+
 /// Load sync databases to determine download size and installed size for each package
 fn add_extra_info(upgrades: &mut [Upgrade]) -> Result<()> {
     let upgrade_pkgs: HashSet<&str> = upgrades.iter().map(|u| &*u.pkgname).collect();
@@ -264,99 +424,438 @@ fn add_extra_info(upgrades: &mut [Upgrade]) -> Result<()> {
             upgrade.download_size = pkg.download_size;
             upgrade.install_size = pkg.install_size;
             upgrade.repo = Some(pkg.repo.clone());
+            upgrade.filename = Some(pkg.filename.clone());
+            upgrade.sha256sum = pkg.sha256sum.clone();
+            upgrade.build_date = pkg.build_date;
+            upgrade.pkgbase = pkg.base.clone();
             match local_sizes.get(&upgrade.pkgname) {
                 Some(size) => upgrade.old_size = *size,
-                None => eprintln!("Warning: couldn't get local size for {}", upgrade.pkgname),
+                None => warning!("couldn't get local size for {}", upgrade.pkgname),
             }
         } else {
-            eprintln!("Warning: package {} not foundin sync DBs", upgrade.pkgname);
+            warning!("package {} not foundin sync DBs", upgrade.pkgname);
         }
     }
     Ok(())
 }
 
-fn run(args: Args) -> Result<()> {
-    let mut upgrades = match args.input {
-        Input::None => get_all_upgrades()?,
+/// Sums of the sizes of a set of upgrades, in bytes
+#[derive(Debug, Default, Serialize)]
+struct Totals {
+    packages: usize,
+    download_size: u64,
+    cached_size: u64,
+    install_size: u64,
+    old_size: u64,
+    net_size: i64,
+    /// Number of upgrades which fix security issues
+    security: usize,
+    /// Number of upgrades of each version class
+    by_class: BTreeMap<VersionClass, usize>,
+}
+
+impl Totals {
+    fn of<'a>(upgrades: impl IntoIterator<Item = &'a Upgrade>) -> Self {
+        let mut totals = upgrades.into_iter().fold(Self::default(), |mut t, u| {
+            t.packages += 1;
+            t.download_size += u.download_size;
+            if u.cached {
+                t.cached_size += u.download_size;
+            }
+            t.install_size += u.install_size;
+            t.old_size += u.old_size;
+            if !u.security.is_empty() {
+                t.security += 1;
+            }
+            *t.by_class.entry(u.class).or_default() += 1;
+            t
+        });
+        totals.net_size = (totals.install_size as i64) - (totals.old_size as i64);
+        totals
+    }
+}
+
+/// Compute the totals for each repo. Upgrades with no known repo are counted under
+/// `Repo::Unknown`, which sorts last.
+fn repo_totals(upgrades: &[Upgrade]) -> BTreeMap<&Repo, Totals> {
+    let mut by_repo: BTreeMap<&Repo, Vec<&Upgrade>> = BTreeMap::new();
+    for u in upgrades {
+        by_repo.entry(u.repo_or_unknown()).or_default().push(u);
+    }
+    by_repo.into_iter().map(|(repo, upgrades)| (repo, Totals::of(upgrades))).collect()
+}
+
+/// The document written by `--format json`
+#[derive(Serialize)]
+struct JsonOutput<'a> {
+    upgrades: &'a [Upgrade],
+    cooling_down: &'a [Upgrade],
+    totals: &'a Totals,
+    by_repo: &'a BTreeMap<&'a Repo, Totals>,
+    /// Upgrades from the previous run which aren't pending any more
+    disappeared: &'a [history::Disappeared],
+    /// What pacman's log says about the pending packages, with `--log`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    log: Option<&'a pacman_log::Log>,
+    /// The cached package file of each package's installed version, with `--rollback`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rollback: Option<&'a BTreeMap<&'a str, Option<PathBuf>>>,
+    /// Unread news items since the last full upgrade, with `--news`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    news: Option<&'a [news::Item]>,
+}
+
+/// Get the upgrades from pacman or the input file, leaving out ignored packages, and add the info
+/// from the sync databases and the package cache
+fn load_upgrades(args: &Args) -> Result<Vec<Upgrade>> {
+    let mut upgrades = match &args.input {
+        Input::None => get_all_upgrades(args.sync, args.show_files)?,
         Input::Stdin => io::read_to_string(io::stdin().lock())
             .context("failed to read stdin")?
             .lines()
             .filter_map(|line| line.parse().ok())
             .collect(),
-        Input::File(path) => fs::read_to_string(&path)
+        Input::File(path) => fs::read_to_string(path)
             .with_context(|| format!("failed to read input file {}", path.display()))?
             .lines()
             .filter_map(|line| line.parse().ok())
             .collect(),
     };
+    upgrades.retain(|u: &Upgrade| !args.ignore.iter().any(|glob| glob_match(glob, &u.pkgname)));
 
     if let Err(err) = add_extra_info(&mut upgrades) {
-        eprintln!("Warning: failed to map packages to repos: {err:#}");
+        warning!("failed to map packages to repos: {err:#}");
+    }
+    check_cached(&mut upgrades, &args.download_dir, args.verify_cache);
+    if args.security {
+        match security::Tracker::load(args.security_file.as_deref()) {
+            Ok(tracker) => tracker.annotate(&mut upgrades),
+            // an empty list would look like there's nothing to fix
+            Err(err) if args.security_only => return Err(err),
+            Err(err) => warning!("failed to load security issues: {err:#}"),
+        }
+    }
+    Ok(upgrades)
+}
+
+fn run(args: Args) -> Result<()> {
+    args.color_choice.write_global();
+    let mut theme = args.theme.clone();
+    let theme_errors = match env::var(theme::ENV_VAR) {
+        Ok(spec) => theme.apply_spec(&spec),
+        Err(_) => Vec::new(),
+    };
+    theme::init(theme);
+    for err in theme_errors {
+        warning!("{}: {err:#}", theme::ENV_VAR);
+    }
+    // whether this run looks at what's pending now, to be recorded in the history, run hooks,
+    // and check free space. Polled formats run too often for that, the daemon runs the hooks
+    // instead, and a list replayed from a file or stdin isn't what's pending now.
+    let live = !args.format.is_polled() && matches!(args.input, Input::None);
+
+    let mut upgrades = match load_upgrades(&args) {
+        Ok(upgrades) => upgrades,
+        Err(err) => {
+            // the metrics still report a failed sync, so it can be alerted on
+            if args.format == OutputFormat::Prometheus || args.prom_file.is_some() {
+                let text = metrics::render(None, checkupdates_db_path(), cooldown::now());
+                match &args.prom_file {
+                    Some(path) => write_atomic(path, text.as_bytes())?,
+                    None => print!("{text}"),
+                }
+            }
+            return Err(err);
+        }
+    };
+
+    let log = if args.log || args.text.columns.contains(&output::Column::LastUpgrade) {
+        let pending: HashSet<&str> = upgrades.iter().map(|u| &*u.pkgname).collect();
+        let log = pacman_log::Log::read(&args.log_file, |p| pending.contains(p))
+            .map_err(|err| warning!("failed to read pacman's log: {err:#}"))
+            .ok();
+        if let Some(log) = &log {
+            for u in upgrades.iter_mut() {
+                u.last_upgrade = log.last_changed.get(&u.pkgname).copied();
+            }
+        }
+        log.filter(|_| args.log)
+    } else {
+        None
+    };
+
+    let news = if args.news {
+        let since = match &log {
+            Some(log) => log.last_full_upgrade,
+            None => pacman_log::Log::read(&args.log_file, |_| false)
+                .map_err(|err| warning!("failed to read pacman's log: {err:#}"))
+                .ok()
+                .and_then(|log| log.last_full_upgrade),
+        };
+        let unread = news::Feed::load(args.news_file.as_deref())
+            .and_then(|feed| Ok((feed.unread(since, &upgrades)?, feed)));
+        match unread {
+            Ok(unread) => Some(unread),
+            // without the feed there's no telling whether it's safe
+            Err(err) if args.ok_to_upgrade => return Err(err),
+            Err(err) => {
+                warning!("failed to load the news: {err:#}");
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let now = cooldown::now();
+    let mut disappeared = Vec::new();
+    let mut previous_run = None;
+    if args.history {
+        let path = history::default_path();
+        match history::History::load(&path) {
+            Ok(mut history) => {
+                history.annotate(&mut upgrades, now);
+                if let Some(previous) = history.previous() {
+                    previous_run = Some(previous.time);
+                    let names: HashSet<&str> =
+                        previous.upgrades.iter().map(|e| &*e.pkgname).collect();
+                    let installed =
+                        alpm::local_package_versions(checkupdates_db_path(), |p| names.contains(p))
+                            .unwrap_or_else(|err| {
+                                warning!("failed to read installed package versions: {err:#}");
+                                Default::default()
+                            });
+                    disappeared = history.disappeared(&upgrades, &installed);
+                }
+                if live {
+                    history.record(&upgrades, now);
+                    if let Err(err) = history.save(&path) {
+                        warning!("failed to save history: {err:#}");
+                    }
+                }
+            }
+            Err(err) => warning!("failed to load history: {err:#}"),
+        }
+    }
+    if let Some(age) = args.since {
+        upgrades.retain(|u| u.first_seen.is_none_or(|seen| seen.saturating_add(age) >= now));
+    }
+
+    if !args.only.is_empty() {
+        upgrades.retain(|u| args.only.contains(&u.class));
+    }
+    if args.security_only {
+        upgrades.retain(|u| !u.security.is_empty());
+    }
+
+    sort::sort_upgrades(&mut upgrades, &args.sort);
+
+    // hold back upgrades which are still cooling down
+    for u in upgrades.iter_mut() {
+        u.ready_at = u.build_date.map(|date| args.cooldown.ready_at(&u.pkgname, date));
     }
+    let (upgrades, cooling): (Vec<Upgrade>, Vec<Upgrade>) =
+        upgrades.into_iter().partition(|u| u.is_ready(now));
+    let totals = Totals::of(&upgrades);
+    let by_repo = repo_totals(&upgrades);
 
-    // sort by repo, then by pkgname
-    upgrades.sort_unstable_by(|a, b| match a.repo.cmp(&b.repo) {
-        std::cmp::Ordering::Equal => a.pkgname.cmp(&b.pkgname),
-        greater_or_less => greater_or_less,
+    if live {
+        hooks::run_all(&args.hooks, &upgrades);
+    }
+
+    let metrics_text =
+        (args.format == OutputFormat::Prometheus || args.prom_file.is_some()).then(|| {
+            let report =
+                metrics::Upgrades { totals: &totals, by_repo: &by_repo, cooling: cooling.len() };
+            metrics::render(Some(&report), checkupdates_db_path(), now)
+        });
+    if let (Some(path), Some(text)) = (&args.prom_file, &metrics_text) {
+        write_atomic(path, text.as_bytes())?;
+    }
+
+    // look for cached copies of the installed versions, to downgrade to if the upgrade breaks
+    let rollback: Option<BTreeMap<&str, Option<PathBuf>>> =
+        (args.rollback || args.require_rollback).then(|| {
+            let mut cache_dirs = pacman_conf::cache_dirs();
+            cache_dirs.push(args.download_dir.clone());
+            let packages = cache::list_packages(&cache_dirs);
+            upgrades
+                .iter()
+                .map(|u| {
+                    let file = cache::find_version(&packages, &u.pkgname, &u.oldver);
+                    (&*u.pkgname, file.map(Path::to_owned))
+                })
+                .collect()
+        });
+    let missing_rollback = rollback.as_ref().map_or(0, |rollback| {
+        upgrades.iter().filter(|u| rollback.get(&*u.pkgname).is_some_and(Option::is_none)).count()
     });
 
-    // the max length of "repo/pkgname" for all upgrades
-    let repo_name_width = upgrades
-        .iter()
-        .map(|u| {
-            let repo_width = match &u.repo {
-                // add 1 for the '/' after the repo name
-                Some(repo) => repo.as_str().len() + 1,
-                None => 0,
+    // the report is buffered so it can be paged, so decide on colors for stdout up front
+    let color_choice = AutoStream::choice(&io::stdout());
+    let mut out = AutoStream::new(Vec::new(), color_choice);
+
+    match args.format {
+        OutputFormat::Text => {
+            output::write_list(&mut out, &upgrades, &cooling, now, &args.text)?;
+            writeln!(out)?;
+            if let (Some(previous_run), false) = (previous_run, disappeared.is_empty()) {
+                output::write_disappeared(
+                    &mut out,
+                    &disappeared,
+                    now.saturating_sub(previous_run),
+                )?;
+                writeln!(out)?;
+            }
+            if args.text.by_repo {
+                output::write_repo_table(&mut out, &by_repo, args.text.sizes)?;
+                writeln!(out)?;
+            }
+            output::write_summary(&mut out, &totals, cooling.len(), args.text.sizes)?;
+            if let Some(rollback) = &rollback {
+                writeln!(out)?;
+                output::write_rollback(&mut out, &upgrades, rollback)?;
+            }
+            if let Some(log) = &log {
+                writeln!(out)?;
+                output::write_log_report(&mut out, log, now)?;
+            }
+            if let Some((unread, _)) = news.as_ref().filter(|(unread, _)| !unread.is_empty()) {
+                writeln!(out)?;
+                output::write_news(&mut out, unread)?;
+            }
+        }
+        OutputFormat::Json => {
+            let doc = JsonOutput {
+                upgrades: &upgrades,
+                cooling_down: &cooling,
+                totals: &totals,
+                by_repo: &by_repo,
+                disappeared: &disappeared,
+                log: log.as_ref(),
+                rollback: rollback.as_ref(),
+                news: news.as_ref().map(|(unread, _)| &**unread),
             };
-            repo_width + u.pkgname.len()
+            serde_json::to_writer_pretty(&mut out, &doc)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Waybar => {
+            let tooltip = statusbar::tooltip(&upgrades, &cooling, now, &args.text)?;
+            let classes = statusbar::classes(&upgrades, args.many);
+            statusbar::write_waybar(&mut out, &upgrades, &tooltip, &classes)?;
+        }
+        OutputFormat::I3blocks => {
+            let classes = statusbar::classes(&upgrades, args.many);
+            statusbar::write_i3blocks(&mut out, &upgrades, &classes)?;
+        }
+        OutputFormat::Count => writeln!(out, "{}", upgrades.len())?,
+        OutputFormat::Prometheus => write!(out, "{}", metrics_text.as_deref().unwrap())?,
+    }
+
+    let mut fits = true;
+    if live {
+        // downloads go to the first pacman cache dir, unless we're downloading them ourselves
+        let download_dir = if args.download {
+            args.download_dir.clone()
+        } else {
+            pacman_conf::cache_dirs().swap_remove(0)
+        };
+        let needs = [
+            (Path::new("/"), totals.net_size.max(0) as u64),
+            (&*download_dir, totals.download_size - totals.cached_size),
+        ];
+        match diskspace::check(&needs) {
+            Ok(spaces) => {
+                for space in spaces.iter().filter(|s| !s.fits()) {
+                    fits = false;
+                    warning!(
+                        "not enough free space on the filesystem of {} \
+                         (need {}, {} available)",
+                        space.path.display(),
+                        args.text.sizes.format(space.needed),
+                        args.text.sizes.format(space.available),
+                    );
+                }
+            }
+            Err(err) => warning!("failed to check free disk space: {err:#}"),
+        }
+    }
+
+    if args.show_files {
+        let pkgnames: Vec<&str> = upgrades.iter().map(|u| &*u.pkgname).collect();
+        let upgrade_pkgs: HashSet<&str> = pkgnames.iter().copied().collect();
+        let changes =
+            alpm::load_sync_files(checkupdates_db_path(), |pkgname| upgrade_pkgs.contains(pkgname))
+                .and_then(|new_files| {
+                    let installed = alpm::local_package_files(checkupdates_db_path(), |_| true)?;
+                    Ok(files::compare(&pkgnames, &new_files, &installed, "/"))
+                });
+        match changes {
+            Ok(changes) => {
+                if changes.len() < pkgnames.len() {
+                    warning!(
+                        "{} packages not found in the .files databases",
+                        pkgnames.len() - changes.len()
+                    );
+                }
+                writeln!(out)?;
+                files::write_changes(&mut out, &changes)?;
+            }
+            Err(err) => warning!("failed to compare package files: {err:#}"),
+        }
+    }
+
+    if args.restart_report {
+        let upgrade_pkgs: HashSet<&str> = upgrades.iter().map(|u| &*u.pkgname).collect();
+        let report = alpm::local_package_files(checkupdates_db_path(), |pkgname| {
+            upgrade_pkgs.contains(pkgname)
         })
-        .max()
-        .unwrap_or(0);
-
-    let oldver_width = upgrades.iter().map(|u| u.oldver.len()).max().unwrap_or(0);
-
-    let mut out = AutoStream::new(io::stdout().lock(), args.color_choice);
-
-    for u in upgrades.iter() {
-        match &u.repo {
-            Some(repo) => write!(
-                out,
-                "{repo}/{pkgname}{space:width$}",
-                repo = repo.color(repo.get_color()),
-                pkgname = u.pkgname,
-                space = "",
-                width = repo_name_width - (u.pkgname.len() + repo.as_str().len() + 1),
-            )?,
-            None => write!(out, "{:repo_name_width$}", u.pkgname)?,
+        .and_then(|files| restart::find_processes("/proc", &files));
+        match report {
+            Ok(report) => {
+                writeln!(out)?;
+                restart::write_report(&mut out, &report)?;
+            }
+            Err(err) => warning!("failed to check running processes: {err:#}"),
         }
+    }
 
-        let clen = u.common_length();
-        writeln!(
-            out,
-            "  {ocommon}{ounique}{space:width$} -> {ncommon}{nunique}",
-            ocommon = &u.oldver[..clen],
-            ounique = (&u.oldver[clen..]).red(),
-            space = "",
-            width = oldver_width - u.oldver.len(),
-            ncommon = &u.newver[..clen],
-            nunique = (&u.newver[clen..]).green(),
-        )?;
-    }
-
-    let (total_dl, total_inst, net_upsize) = {
-        let (dl, inst, old) = upgrades.iter().fold((0, 0, 0), |(dl, inst, old), u| {
-            (dl + u.download_size, inst + u.install_size, old + u.old_size)
-        });
-        let net = (inst as i64) - (old as i64);
-        (dl as f32 / 1048576.0, inst as f32 / 1048576.0, net as f32 / 1048576.0)
+    let paging = match args.format {
+        OutputFormat::Text => args.paging,
+        _ => pager::Paging::Never,
     };
+    pager::write(&out.into_inner(), paging)?;
+    let mut unread_news = 0;
+    if let Some((unread, feed)) = &news {
+        if args.ack_news {
+            feed.acknowledge(unread).context("failed to record the news as read")?;
+        } else {
+            unread_news = unread.len();
+        }
+    }
+    if unread_news > 0 && args.ok_to_upgrade {
+        return Err(anyhow!(
+            "{unread_news} unread news items since the last upgrade, read them and acknowledge \
+             with --ack-news"
+        ));
+    }
+    if !fits && args.require_space {
+        return Err(anyhow!("not enough free disk space for the upgrade"));
+    }
+    if missing_rollback > 0 && args.require_rollback {
+        return Err(anyhow!(
+            "no cached copy of the installed version of {missing_rollback} packages to roll back to"
+        ));
+    }
 
-    writeln!(out)?;
-    writeln!(out, "Packages to upgrade:  {:5}", upgrades.len())?;
-    writeln!(out, "Total download size:  {total_dl:8.2} MiB")?;
-    writeln!(out, "Total installed size: {total_inst:8.2} MiB")?;
-    writeln!(out, "Net upgrade size:     {net_upsize:8.2} MiB")?;
+    if args.download {
+        let mut out = AutoStream::new(io::stdout().lock(), color_choice);
+        writeln!(out)?;
+        let pending: Vec<Upgrade> = upgrades.into_iter().filter(|u| !u.cached).collect();
+        let downloader =
+            download::Downloader::new(&args.download_dir, args.progress, args.text.sizes)?;
+        downloader.download_all(&mut out, &pending)?;
+    }
 
     Ok(())
 }
@@ -372,6 +871,55 @@ package) to fetch a copy of the sync databases and list available updates for in
 You may instead use a file containing the same output format as `pacman -Qu` as the input, though
 this is mainly for testing.";
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+    Waybar,
+    I3blocks,
+    Count,
+    Prometheus,
+}
+
+impl OutputFormat {
+    /// Whether this is one of the formats for status bars and monitoring, which are polled
+    /// periodically, so they don't record the history or run hooks
+    fn is_polled(self) -> bool {
+        matches!(self, Self::Waybar | Self::I3blocks | Self::Count | Self::Prometheus)
+    }
+}
+
+impl clap::ValueEnum for OutputFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Text, Self::Json, Self::Waybar, Self::I3blocks, Self::Count, Self::Prometheus]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Text => PossibleValue::new("text").help("List of upgrades and a summary"),
+            Self::Json => PossibleValue::new("json").help("JSON document with all upgrade info"),
+            Self::Waybar => PossibleValue::new("waybar")
+                .help("JSON for a waybar custom module, with the list as its tooltip"),
+            Self::I3blocks => PossibleValue::new("i3blocks")
+                .help("Full text, short text, and color lines for i3blocks or polybar"),
+            Self::Count => PossibleValue::new("count").help("Just the number of upgrades"),
+            Self::Prometheus => PossibleValue::new("prometheus")
+                .help("Prometheus metrics, for node_exporter's textfile collector"),
+        })
+    }
+}
+
+/// What to do, picked by the subcommand
+#[derive(Clone)]
+enum Mode {
+    /// List the upgrades, when there's no subcommand
+    Check,
+    /// Run the daemon, see the `daemon` module
+    Daemon { interval: u64, socket: PathBuf },
+    /// Ask the daemon
+    Query { query: daemon::Query, socket: PathBuf },
+}
+
 enum Input {
     None,
     Stdin,
@@ -379,20 +927,417 @@ enum Input {
 }
 
 struct Args {
+    mode: Mode,
     color_choice: ColorChoice,
+    paging: pager::Paging,
+    format: OutputFormat,
+    sort: Vec<sort::SortKey>,
+    only: Vec<VersionClass>,
+    /// Globs of package names to leave out
+    ignore: Vec<String>,
+    /// Refresh the copy of the sync databases before checking
+    sync: bool,
+    /// How many upgrades the status bar formats call many
+    many: usize,
+    /// Commands to run when there are upgrades
+    hooks: Vec<hooks::Hook>,
+    /// Where to write Prometheus metrics, besides the output
+    prom_file: Option<PathBuf>,
+    /// Match upgrades against the security tracker
+    security: bool,
+    /// The tracker's issue list, instead of fetching it
+    security_file: Option<PathBuf>,
+    /// Only show upgrades which fix security issues
+    security_only: bool,
+    /// Compare with and record to the history of runs
+    history: bool,
+    /// Only show upgrades first seen within this many seconds
+    since: Option<u64>,
+    /// Report what pacman's log says about the pending packages
+    log: bool,
+    log_file: PathBuf,
+    /// Show unread Arch news since the last full upgrade
+    news: bool,
+    /// The news feed, instead of fetching it
+    news_file: Option<PathBuf>,
+    /// Record the unread news shown as read
+    ack_news: bool,
+    /// Fail while there's unread news
+    ok_to_upgrade: bool,
+    theme: theme::Theme,
+    text: output::TextOptions,
     input: Input,
+    verify_cache: bool,
+    show_files: bool,
+    restart_report: bool,
+    download: bool,
+    download_dir: PathBuf,
+    progress: bool,
+    require_space: bool,
+    /// Report which packages have their installed version cached
+    rollback: bool,
+    /// Fail when a package has no cached copy of its installed version
+    require_rollback: bool,
+    cooldown: cooldown::Policy,
 }
 
 impl Args {
-    fn parse() -> Self {
-        let mut args = clap::command!()
+    /// The command line interface, before the config file sets its defaults
+    fn command() -> clap::Command {
+        let cmd = clap::command!()
             .about(HELP_TEXT.lines().next().unwrap())
             .long_about(HELP_TEXT)
+            .arg(
+                Arg::new("color")
+                    .long("color")
+                    .value_parser(clap::value_parser!(clap::ColorChoice))
+                    .default_value("auto")
+                    .help(
+                        "When to use colors, auto uses them on terminals and honors NO_COLOR, \
+                         CLICOLOR, CLICOLOR_FORCE, and TERM=dumb",
+                    ),
+            )
             .arg(
                 Arg::new("no-color")
                     .long("no-color")
                     .action(ArgAction::SetTrue)
-                    .help("Disable colored output"),
+                    .help("Disable colored output, same as --color never"),
+            )
+            .arg(
+                Arg::new("paging")
+                    .long("paging")
+                    .value_parser(clap::value_parser!(pager::Paging))
+                    .default_value("auto")
+                    .help("When to show the text output in $PAGER"),
+            )
+            .arg(
+                Arg::new("format")
+                    .long("format")
+                    .value_parser(clap::value_parser!(OutputFormat))
+                    .default_value("text")
+                    .help("Output format"),
+            )
+            .arg(
+                Arg::new("sort")
+                    .long("sort")
+                    .value_parser(sort::SortKey::parse)
+                    .value_name("KEY[:asc|:desc],...")
+                    .value_delimiter(',')
+                    .action(ArgAction::Append)
+                    .help("Sort the upgrade list by these keys, default repo,name")
+                    .long_help(
+                        "Sort the upgrade list by these keys, in order of priority. Keys are \
+                         repo, name, download, installed, net, builddate, and class, each \
+                         optionally followed by :asc or :desc. The default is repo,name.",
+                    ),
+            )
+            .arg(
+                Arg::new("only")
+                    .long("only")
+                    .value_parser(clap::value_parser!(VersionClass))
+                    .value_name("CLASS")
+                    .value_delimiter(',')
+                    .action(ArgAction::Append)
+                    .help("Only show upgrades with these kinds of version change"),
+            )
+            .arg(
+                Arg::new("show-class").long("show-class").action(ArgAction::SetTrue).help(
+                    "Show the kind of version change for each upgrade, same as --columns class",
+                ),
+            )
+            .arg(
+                Arg::new("columns")
+                    .short('c')
+                    .long("columns")
+                    .value_parser(clap::value_parser!(output::Column))
+                    .value_name("COLUMN")
+                    .value_delimiter(',')
+                    .action(ArgAction::Append)
+                    .help("Extra columns to show for each upgrade"),
+            )
+            .arg(
+                Arg::new("units")
+                    .long("units")
+                    .value_parser(clap::value_parser!(size::Unit))
+                    .default_value("m")
+                    .help("Unit for sizes"),
+            )
+            .arg(
+                Arg::new("width")
+                    .long("width")
+                    .value_parser(clap::value_parser!(usize))
+                    .value_name("COLUMNS")
+                    .help(
+                        "Fit the list into this many columns, 0 for no limit \
+                         [default: terminal width]",
+                    ),
+            )
+            .arg(
+                Arg::new("si")
+                    .long("si")
+                    .action(ArgAction::SetTrue)
+                    .help("Use powers of 1000 (kB, MB, GB) rather than 1024 for sizes"),
+            )
+            .arg(
+                Arg::new("by-repo")
+                    .long("by-repo")
+                    .action(ArgAction::SetTrue)
+                    .help("Show upgrades in a section per repo, with subtotals"),
+            )
+            .arg(
+                Arg::new("group")
+                    .short('g')
+                    .long("group")
+                    .action(ArgAction::SetTrue)
+                    .help("Collapse split packages into one line per pkgbase"),
+            )
+            .arg(
+                Arg::new("expand")
+                    .long("expand")
+                    .action(ArgAction::SetTrue)
+                    .requires("group")
+                    .help("With --group, list the packages in each group"),
+            )
+            .arg(
+                Arg::new("min-age")
+                    .long("min-age")
+                    .value_parser(cooldown::parse_age)
+                    .value_name("AGE")
+                    .help("Hold back packages built less than AGE ago (e.g. 3d, 12h, 1w)"),
+            )
+            .arg(
+                Arg::new("min-age-for")
+                    .long("min-age-for")
+                    .value_parser(cooldown::parse_override)
+                    .value_name("PKGNAME=AGE")
+                    .action(ArgAction::Append)
+                    .help("Override --min-age for one package, may be repeated"),
+            )
+            .arg(
+                Arg::new("verify-cache")
+                    .long("verify-cache")
+                    .action(ArgAction::SetTrue)
+                    .help("Check the SHA256 of package files found in the pacman cache"),
+            )
+            .arg(
+                Arg::new("files")
+                    .long("files")
+                    .action(ArgAction::SetTrue)
+                    .help("Show files added or removed by each upgrade, and file conflicts")
+                    .long_help(
+                        "Show files added or removed by each upgrade, and files which would \
+                         conflict with other installed packages. This syncs and reads the \
+                         .files databases, which are much larger than the regular ones.",
+                    ),
+            )
+            .arg(
+                Arg::new("restart-report")
+                    .long("restart-report")
+                    .action(ArgAction::SetTrue)
+                    .help("List running processes which will need restarting after the upgrade"),
+            )
+            .arg(
+                Arg::new("download")
+                    .short('d')
+                    .long("download")
+                    .action(ArgAction::SetTrue)
+                    .help("Download pending upgrades into the download directory"),
+            )
+            .arg(
+                Arg::new("download-dir")
+                    .long("download-dir")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .value_name("DIR")
+                    .help(
+                        "Directory for --download, default $XDG_CACHE_HOME/checkupgrades/pkg. \
+                         Add it as a CacheDir in pacman.conf to install from it.",
+                    ),
+            )
+            .arg(
+                Arg::new("require-space")
+                    .long("require-space")
+                    .action(ArgAction::SetTrue)
+                    .help("Exit with an error if there isn't enough free disk space to upgrade"),
+            )
+            .arg(Arg::new("rollback").long("rollback").action(ArgAction::SetTrue).help(
+                "Report which packages have a copy of their installed version in the \
+                 package cache, to roll back to if the upgrade goes wrong",
+            ))
+            .arg(
+                Arg::new("require-rollback")
+                    .long("require-rollback")
+                    .action(ArgAction::SetTrue)
+                    .help(
+                        "Exit with an error if a package has no cached copy of its installed \
+                         version (implies --rollback)",
+                    ),
+            )
+            .arg(Arg::new("security").long("security").action(ArgAction::SetTrue).help(
+                "Show the security issues fixed by upgrades, from the Arch Linux \
+                 security tracker",
+            ))
+            .arg(
+                Arg::new("security-file")
+                    .long("security-file")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .value_name("FILE")
+                    .help(
+                        "Read the security tracker's issue list (issues/all.json) from FILE \
+                         instead of fetching it (implies --security)",
+                    ),
+            )
+            .arg(
+                Arg::new("security-only")
+                    .long("security-only")
+                    .action(ArgAction::SetTrue)
+                    .help("Only show upgrades which fix security issues (implies --security)"),
+            )
+            .arg(Arg::new("news").long("news").action(ArgAction::SetTrue).help(
+                "Show Arch Linux news published since the last full upgrade which \
+                 hasn't been acknowledged, highlighting items mentioning pending packages",
+            ))
+            .arg(
+                Arg::new("news-file")
+                    .long("news-file")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .value_name("FILE")
+                    .help(
+                        "Read the news RSS feed from FILE instead of fetching it (implies --news)",
+                    ),
+            )
+            .arg(
+                Arg::new("ack-news")
+                    .long("ack-news")
+                    .action(ArgAction::SetTrue)
+                    .help("Acknowledge the unread news shown, so it isn't shown again"),
+            )
+            .arg(Arg::new("ok-to-upgrade").long("ok-to-upgrade").action(ArgAction::SetTrue).help(
+                "Exit with an error while there is unread news, for scripts which \
+                 upgrade (implies --news)",
+            ))
+            .arg(
+                Arg::new("ignore")
+                    .long("ignore")
+                    .value_name("PKG")
+                    .value_delimiter(',')
+                    .action(ArgAction::Append)
+                    .help("Leave out packages matching these globs, like pacman's IgnorePkg"),
+            )
+            .arg(
+                Arg::new("since")
+                    .long("since")
+                    .value_parser(cooldown::parse_age)
+                    .value_name("AGE")
+                    .conflicts_with("no-history")
+                    .help("Only show upgrades which first appeared within AGE, e.g. 12h or 2d"),
+            )
+            .arg(Arg::new("no-history").long("no-history").action(ArgAction::SetTrue).help(
+                "Don't compare with or record to the history of runs, which is used to \
+                 mark new upgrades",
+            ))
+            .arg(Arg::new("log").long("log").action(ArgAction::SetTrue).help(
+                "Report the last full system upgrade, and past downgrades of pending packages, \
+                 from pacman's log",
+            ))
+            .arg(
+                Arg::new("log-file")
+                    .long("log-file")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .value_name("PATH")
+                    .help("pacman's log file [default: LogFile from pacman.conf]"),
+            )
+            .arg(Arg::new("no-sync").long("no-sync").action(ArgAction::SetTrue).help(
+                "Use the copy of the sync databases from the last run without refreshing \
+                 it, which is quick and works offline. The daemon always syncs.",
+            ))
+            .arg(
+                Arg::new("many")
+                    .long("many")
+                    .value_parser(clap::value_parser!(usize))
+                    .value_name("COUNT")
+                    .default_value("50")
+                    .help(
+                        "Number of upgrades from which the status bar formats add the many class",
+                    ),
+            )
+            .arg(
+                Arg::new("hook")
+                    .long("hook")
+                    .value_parser(hooks::parse)
+                    .value_name("EVENT=COMMAND")
+                    .action(ArgAction::Append)
+                    .help(
+                        "Run COMMAND with the upgrades as JSON on stdin when EVENT happens: \
+                         available (there are upgrades), new (since the previous run), or \
+                         security (upgrades fix security issues, with --security). May be \
+                         repeated.",
+                    )
+                    .long_help(
+                        "Run COMMAND with `sh -c` when EVENT happens, with the upgrades which \
+                         triggered it as JSON on stdin. EVENT is available (there are \
+                         upgrades), new (there are upgrades which weren't there in the previous \
+                         run), or security (there are upgrades which fix security issues, with \
+                         --security). May be repeated, e.g.\n\
+                         --hook 'new=notify-send \"$CHECKUPGRADES_NEW new upgrades\"'\n\
+                         --hook 'security=notify-send -u critical \"Security upgrades\"'",
+                    ),
+            )
+            .arg(
+                Arg::new("no-hooks")
+                    .long("no-hooks")
+                    .action(ArgAction::SetTrue)
+                    .help("Don't run any hooks"),
+            )
+            .arg(
+                Arg::new("prom-file")
+                    .long("prom-file")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .value_name("PATH")
+                    .help(
+                        "Also write Prometheus metrics to PATH, replacing it atomically, e.g. in \
+                         node_exporter's textfile collector directory",
+                    ),
+            )
+            .arg(
+                Arg::new("db-path")
+                    .long("db-path")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .value_name("DIR")
+                    .help(
+                        "Where to keep the copy of the sync databases [env: CHECKUPDATES_DB] \
+                         [default: $TMPDIR/checkup-db-$UID]",
+                    ),
+            )
+            .arg(
+                Arg::new("print-config").long("print-config").action(ArgAction::SetTrue).help(
+                    "Print the effective settings from the config file and options, and exit",
+                ),
+            )
+            .subcommand(
+                clap::Command::new("daemon")
+                    .about(
+                        "Sync periodically in the background and answer queries on a Unix socket",
+                    )
+                    .arg(
+                        Arg::new("interval")
+                            .long("interval")
+                            .value_parser(cooldown::parse_age)
+                            .value_name("AGE")
+                            .default_value("1h")
+                            .help("How often to sync, e.g. 30m or 2h"),
+                    )
+                    .arg(socket_arg()),
+            )
+            .subcommand(
+                clap::Command::new("query")
+                    .about("Ask the daemon about the upgrades it found")
+                    .arg(
+                        Arg::new("query")
+                            .required(true)
+                            .value_parser(clap::value_parser!(daemon::Query))
+                            .help("What to ask"),
+                    )
+                    .arg(socket_arg()),
             )
             .arg(
                 Arg::new("upgrades-file")
@@ -403,14 +1348,143 @@ impl Args {
                         "Read list of upgrades from FILE (or stdin when FILE is '-') \
                          instead of `pacman -Qu`",
                     ),
-            )
-            .get_matches();
+            );
+        config::add_negations(cmd)
+    }
+
+    fn parse() -> Result<Self> {
+        let config_path = config::path();
+        let config = config::Config::load(&config_path)?;
+        let mut theme = theme::Theme::default();
+        for (key, sgr) in config.theme.iter() {
+            theme::parse_sgr(sgr).and_then(|style| theme.set(key, style)).with_context(|| {
+                format!("invalid theme entry {key:?} in {}", config_path.display())
+            })?;
+        }
+
+        let cmd = Self::command();
+        config
+            .validate(&cmd)
+            .with_context(|| format!("invalid value in {}", config_path.display()))?;
+        let mut cmd = config.apply_defaults(cmd).after_help(format!(
+            "Defaults for most options can be set in {} (or ${}). A flag set there is \
+             undone by --no-FLAG, and sync = false or history = false by --sync or --history.",
+            config_path.display(),
+            config::ENV_VAR
+        ));
+        let mut args = cmd.get_matches_mut();
+
+        let db_path = args
+            .remove_one::<PathBuf>("db-path")
+            .or_else(|| env::var_os("CHECKUPDATES_DB").map(PathBuf::from))
+            .or(config.db_path);
+        if let Some(db_path) = db_path {
+            let _ = CHECKUPDATES_DB.set(db_path);
+        }
+        if args.get_flag("print-config") {
+            let mut theme_entries = config.theme;
+            if let Ok(spec) = env::var(theme::ENV_VAR) {
+                let entries = spec.split(':').filter_map(|entry| entry.split_once('='));
+                theme_entries.extend(entries.map(|(k, v)| (k.to_owned(), v.to_owned())));
+            }
+            let mut effective =
+                config::Config::effective(&args, checkupdates_db_path(), theme_entries);
+            effective.download_dir.get_or_insert_with(default_download_dir);
+            effective.log_file.get_or_insert_with(pacman_conf::log_file);
+            print!("{}", toml::to_string(&effective).context("failed to format config")?);
+            std::process::exit(0);
+        }
 
-        Self {
-            color_choice: if args.get_flag("no-color") || anstyle_query::no_color() {
-                ColorChoice::Never
+        let format = *args.get_one::<OutputFormat>("format").unwrap();
+        if format != OutputFormat::Text {
+            for flag in ["files", "restart-report", "download"] {
+                if args.get_flag(flag) {
+                    cmd.error(
+                        clap::error::ErrorKind::ArgumentConflict,
+                        format!("--{flag} can only be used with --format text"),
+                    )
+                    .exit();
+                }
+            }
+        }
+
+        let socket = |sub: &mut clap::ArgMatches| {
+            sub.remove_one::<PathBuf>("socket").unwrap_or_else(daemon::default_socket_path)
+        };
+        let mode = match args.remove_subcommand() {
+            Some((name, mut sub)) if name == "daemon" => Mode::Daemon {
+                interval: sub.remove_one::<u64>("interval").unwrap(),
+                socket: socket(&mut sub),
+            },
+            Some((_, mut sub)) => Mode::Query {
+                query: sub.remove_one::<daemon::Query>("query").unwrap(),
+                socket: socket(&mut sub),
+            },
+            None => Mode::Check,
+        };
+
+        Ok(Self {
+            mode,
+            color_choice: match args.get_one::<clap::ColorChoice>("color").unwrap() {
+                _ if args.get_flag("no-color") => ColorChoice::Never,
+                clap::ColorChoice::Auto => ColorChoice::Auto,
+                clap::ColorChoice::Always => ColorChoice::Always,
+                clap::ColorChoice::Never => ColorChoice::Never,
+            },
+            paging: *args.get_one::<pager::Paging>("paging").unwrap(),
+            format,
+            sort: args
+                .remove_many::<sort::SortKey>("sort")
+                .map(Iterator::collect)
+                .unwrap_or_else(sort::SortKey::default_order),
+            only: args.remove_many::<VersionClass>("only").into_iter().flatten().collect(),
+            ignore: args.remove_many::<String>("ignore").into_iter().flatten().collect(),
+            sync: config::flag(&args, "sync"),
+            many: *args.get_one::<usize>("many").unwrap(),
+            prom_file: args.remove_one::<PathBuf>("prom-file"),
+            security: config::flag(&args, "security")
+                || config::flag(&args, "security-only")
+                || args.contains_id("security-file"),
+            security_file: args.remove_one::<PathBuf>("security-file"),
+            security_only: config::flag(&args, "security-only"),
+            news: config::flag(&args, "news")
+                || args.get_flag("ack-news")
+                || config::flag(&args, "ok-to-upgrade")
+                || args.contains_id("news-file"),
+            news_file: args.remove_one::<PathBuf>("news-file"),
+            ack_news: args.get_flag("ack-news"),
+            ok_to_upgrade: config::flag(&args, "ok-to-upgrade"),
+            hooks: if args.get_flag("no-hooks") {
+                Vec::new()
             } else {
-                ColorChoice::Always
+                args.remove_many::<hooks::Hook>("hook").into_iter().flatten().collect()
+            },
+            history: config::flag(&args, "history"),
+            since: args.remove_one::<u64>("since"),
+            log: config::flag(&args, "log"),
+            log_file: args.remove_one::<PathBuf>("log-file").unwrap_or_else(pacman_conf::log_file),
+            theme,
+            text: output::TextOptions {
+                columns: {
+                    let mut columns: Vec<output::Column> =
+                        args.remove_many("columns").into_iter().flatten().collect();
+                    if args.get_flag("show-class") && !columns.contains(&output::Column::Class) {
+                        columns.push(output::Column::Class);
+                    }
+                    columns
+                },
+                sizes: size::SizeFormat {
+                    unit: *args.get_one::<size::Unit>("units").unwrap(),
+                    si: config::flag(&args, "si"),
+                },
+                group: config::flag(&args, "group"),
+                expand_groups: config::flag(&args, "expand"),
+                by_repo: config::flag(&args, "by-repo"),
+                width: match args.remove_one::<usize>("width") {
+                    Some(0) => None,
+                    Some(width) => Some(width),
+                    None => output::terminal_width(),
+                },
             },
 
             input: args.remove_one::<PathBuf>("upgrades-file").map_or(Input::None, |path| {
@@ -420,12 +1494,42 @@ impl Args {
                     Input::File(path)
                 }
             }),
-        }
+
+            verify_cache: config::flag(&args, "verify-cache"),
+            show_files: args.get_flag("files"),
+            restart_report: args.get_flag("restart-report"),
+            download: args.get_flag("download"),
+            download_dir: args
+                .remove_one::<PathBuf>("download-dir")
+                .unwrap_or_else(default_download_dir),
+            progress: io::stderr().is_terminal(),
+            require_space: config::flag(&args, "require-space"),
+            rollback: config::flag(&args, "rollback"),
+            require_rollback: config::flag(&args, "require-rollback"),
+            cooldown: cooldown::Policy::new(
+                args.remove_one::<u64>("min-age").unwrap_or(0),
+                args.remove_many::<(String, u64)>("min-age-for").into_iter().flatten(),
+            ),
+        })
     }
 }
 
+/// The `--socket` option of the daemon and query subcommands
+fn socket_arg() -> Arg {
+    Arg::new("socket")
+        .long("socket")
+        .value_parser(clap::value_parser!(PathBuf))
+        .value_name("PATH")
+        .help("The daemon's socket [default: $XDG_RUNTIME_DIR/checkupgrades.sock]")
+}
+
 fn main() {
-    if let Err(err) = run(Args::parse()) {
+    let result = Args::parse().and_then(|args| match args.mode.clone() {
+        Mode::Check => run(args),
+        Mode::Daemon { interval, socket } => daemon::serve(args, interval, &socket),
+        Mode::Query { query, socket } => daemon::query(&socket, query),
+    });
+    if let Err(err) = result {
         if let Some(ioerr) = err.downcast_ref::<io::Error>() {
             if ioerr.kind() == io::ErrorKind::BrokenPipe {
                 return;
@@ -435,3 +1539,37 @@ fn main() {
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        let cases = [
+            ("gcc", "gcc", true),
+            ("gcc", "gcc-libs", false),
+            ("gcc", "lib32-gcc", false),
+            ("linux*", "linux", true),
+            ("linux*", "linux-lts", true),
+            ("linux*", "lib32-linux", false),
+            ("*-git", "foo-git", true),
+            ("*-git", "foo-git2", false),
+            ("*-testing", "core-testing", true),
+            ("a*b*c", "aXbYbZc", true),
+            ("a*b*c", "aXbYbZ", false),
+            ("**", "x", true),
+            ("*", "", true),
+            ("py?hon", "python", true),
+            ("py?hon", "pyhon", false),
+            ("?", "é", true),
+            ("?", "", false),
+            ("?", "ab", false),
+            ("", "", true),
+            ("", "a", false),
+        ];
+        for (glob, s, matches) in cases {
+            assert_eq!(glob_match(glob, s), matches, "{glob:?} {s:?}");
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Prometheus metrics in the text exposition format, for node_exporter's textfile collector
+//!
+//! The outcome of syncs is kept in `$XDG_STATE_HOME/checkupgrades/sync.json`, so the time of the
+//! last successful sync and the failure counter survive between runs. Metrics are still written
+//! when a sync fails, with just the sync metrics, so the failure can be alerted on.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::{warning, Repo, Totals};
+
+/// The outcome of past syncs
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SyncStatus {
+    /// When the last successful sync finished, in seconds since the Unix epoch
+    last_success: Option<u64>,
+    /// How many syncs have failed
+    failures: u64,
+}
+
+impl SyncStatus {
+    fn path() -> PathBuf {
+        crate::xdg_dir("XDG_STATE_HOME", ".local/state").join("checkupgrades/sync.json")
+    }
+
+    /// Load the status, a missing file means no syncs yet
+    fn load() -> Result<Self> {
+        crate::load_state(&Self::path(), "sync status")
+    }
+
+    /// Record the outcome of a sync finished at `now`, warning if it can't be saved
+    pub fn record(success: bool, now: u64) {
+        let result = Self::load().and_then(|mut status| {
+            if success {
+                status.last_success = Some(now);
+            } else {
+                status.failures += 1;
+            }
+            crate::write_atomic(&Self::path(), serde_json::to_string(&status)?.as_bytes())
+        });
+        if let Err(err) = result {
+            warning!("failed to record the sync status: {err:#}");
+        }
+    }
+}
+
+/// Seconds since the newest sync database in the copy at `db_path` was modified, which pacman
+/// sets to when the mirror last changed it
+fn database_age(db_path: &Path, now: u64) -> Option<u64> {
+    let newest = fs::read_dir(db_path.join("sync"))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "db"))
+        .filter_map(|entry| entry.metadata().ok()?.modified().ok())
+        .max()?;
+    Some(now.saturating_sub(newest.duration_since(UNIX_EPOCH).ok()?.as_secs()))
+}
+
+/// The upgrade numbers to report, from the same data as the summary
+pub struct Upgrades<'a> {
+    pub totals: &'a Totals,
+    pub by_repo: &'a BTreeMap<&'a Repo, Totals>,
+    pub cooling: usize,
+}
+
+/// Escape a label value
+fn escape_label(value: &str) -> String {
+    value.replace('\\', r"\\").replace('"', r#"\""#).replace('\n', r"\n")
+}
+
+/// Format the metrics at time `now` for the copy of the sync databases at `db_path`. `upgrades`
+/// is `None` when they couldn't be found because the sync failed.
+pub fn render(upgrades: Option<&Upgrades>, db_path: &Path, now: u64) -> String {
+    let sync = SyncStatus::load().unwrap_or_else(|err| {
+        warning!("{err:#}");
+        SyncStatus::default()
+    });
+    let mut out = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, samples: &[(String, String)]| {
+        let _ = writeln!(out, "# HELP checkupgrades_{name} {help}");
+        let _ = writeln!(out, "# TYPE checkupgrades_{name} {kind}");
+        for (labels, value) in samples {
+            let _ = writeln!(out, "checkupgrades_{name}{labels} {value}");
+        }
+    };
+    let one = |value: String| [(String::new(), value)];
+
+    if let Some(upgrades) = upgrades {
+        let by_repo: Vec<(String, String)> = upgrades
+            .by_repo
+            .iter()
+            .map(|(repo, totals)| {
+                (
+                    format!("{{repo=\"{}\"}}", escape_label(repo.as_str())),
+                    totals.packages.to_string(),
+                )
+            })
+            .collect();
+        metric("upgrades", "gauge", "Pending upgrades by repo", &by_repo);
+        // the by repo gauge has no samples when up to date, this one is 0 then
+        metric(
+            "upgrades_total",
+            "gauge",
+            "Pending upgrades in all repos",
+            &one(upgrades.totals.packages.to_string()),
+        );
+        metric(
+            "cooling_down_upgrades",
+            "gauge",
+            "Upgrades held back until they're old enough",
+            &one(upgrades.cooling.to_string()),
+        );
+        let totals = upgrades.totals;
+        metric(
+            "security_upgrades",
+            "gauge",
+            "Pending upgrades which fix security issues",
+            &one(totals.security.to_string()),
+        );
+        metric(
+            "download_bytes",
+            "gauge",
+            "Total download size of the pending upgrades",
+            &one(totals.download_size.to_string()),
+        );
+        metric(
+            "cached_bytes",
+            "gauge",
+            "Download size of the pending upgrades already in the package cache",
+            &one(totals.cached_size.to_string()),
+        );
+        metric(
+            "installed_bytes",
+            "gauge",
+            "Total installed size of the new versions",
+            &one(totals.install_size.to_string()),
+        );
+        metric(
+            "net_bytes",
+            "gauge",
+            "Change in installed size after the upgrade",
+            &one(totals.net_size.to_string()),
+        );
+    }
+    if let Some(age) = database_age(db_path, now) {
+        metric(
+            "database_age_seconds",
+            "gauge",
+            "Age of the newest sync database",
+            &one(age.to_string()),
+        );
+    }
+    if let Some(time) = sync.last_success {
+        metric(
+            "last_sync_timestamp_seconds",
+            "gauge",
+            "When the sync databases were last synced successfully",
+            &one(time.to_string()),
+        );
+    }
+    metric(
+        "sync_failures_total",
+        "counter",
+        "Syncs of the databases which failed",
+        &one(sync.failures.to_string()),
+    );
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn up_to_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let upgrades =
+            Upgrades { totals: &Totals::default(), by_repo: &BTreeMap::new(), cooling: 0 };
+        let out = render(Some(&upgrades), dir.path(), 0);
+        let samples: Vec<&str> = out.lines().filter(|line| !line.starts_with('#')).collect();
+        assert!(samples.contains(&"checkupgrades_upgrades_total 0"), "{out}");
+        assert!(!samples.iter().any(|s| s.starts_with("checkupgrades_upgrades{")), "{out}");
+        assert!(samples.contains(&"checkupgrades_download_bytes 0"), "{out}");
+    }
+}
@@ -0,0 +1,244 @@
+//! The Arch Linux news feed, which announces upgrades that need manual intervention
+//!
+//! The RSS feed is fetched from `FEED_URL` and cached for an hour in
+//! `$XDG_CACHE_HOME/checkupgrades/news.xml`, or read from a local file. Items published after the
+//! last full upgrade in pacman's log are unread until acknowledged with `--ack-news`, which is
+//! recorded in `$XDG_STATE_HOME/checkupgrades/news-read.json`.
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{download, pacman_log, regex, Upgrade};
+
+/// The news feed of archlinux.org
+pub const FEED_URL: &str = "https://archlinux.org/feeds/news/";
+
+/// How long a fetched feed is used before fetching it again, in seconds
+const MAX_CACHE_AGE: u64 = 3600;
+
+/// An item of the feed
+#[derive(Debug, Clone, Serialize)]
+pub struct Item {
+    pub title: String,
+    pub link: String,
+    /// When it was published, in seconds since the Unix epoch
+    pub published: Option<u64>,
+    /// The pending packages it mentions by name
+    pub mentions: Vec<String>,
+    /// Identifies the item when acknowledging it, the guid or else the link
+    #[serde(skip)]
+    id: String,
+    /// The description without its HTML tags, to look for package names in
+    #[serde(skip)]
+    text: String,
+}
+
+/// The IDs of the items acknowledged as read
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ReadItems {
+    read: BTreeSet<String>,
+}
+
+impl ReadItems {
+    fn path() -> PathBuf {
+        crate::xdg_dir("XDG_STATE_HOME", ".local/state").join("checkupgrades/news-read.json")
+    }
+
+    /// Load the acknowledged items, a missing file means none
+    fn load() -> Result<Self> {
+        crate::load_state(&Self::path(), "list of read news")
+    }
+}
+
+/// The items of the feed, newest first like the feed lists them
+#[derive(Debug)]
+pub struct Feed {
+    items: Vec<Item>,
+}
+
+/// Parse an RFC 2822 date like `Sat, 05 Oct 2024 10:00:00 +0000` into seconds since the Unix
+/// epoch
+fn parse_date(s: &str) -> Option<u64> {
+    let re = regex!(
+        r"(?x)
+        ^(?:[A-Za-z]{3},\ )?                  # day of the week
+        (\d{1,2})\ ([A-Za-z]{3})\ (\d{4})\    # date
+        (\d{2}):(\d{2})(?::(\d{2}))?\         # time
+        (?:([+-])(\d{2})(\d{2})|GMT|UTC?|Z)$  # offset from UTC
+    "
+    );
+    let caps = re.captures(s.trim())?;
+    let num = |i: usize| caps.get(i).map_or(0, |m| m.as_str().parse::<i64>().unwrap());
+    const MONTHS: [&str; 12] =
+        ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(&caps[2]))? as u32 + 1;
+    let days = pacman_log::days_from_civil(num(3), month, num(1) as u32);
+    let mut secs = days * 86400 + num(4) * 3600 + num(5) * 60 + num(6);
+    let offset = num(8) * 3600 + num(9) * 60;
+    match caps.get(7).map(|m| m.as_str()) {
+        Some("+") => secs -= offset,
+        Some(_) => secs += offset,
+        None => {}
+    }
+    u64::try_from(secs).ok()
+}
+
+/// Whether `text` contains `word` not as part of a longer package name
+fn mentions(text: &str, word: &str) -> bool {
+    let is_name_char = |c: char| c.is_ascii_alphanumeric() || "@._+-".contains(c);
+    text.match_indices(word).any(|(start, _)| {
+        let rest = &text[start + word.len()..];
+        // a dot only continues the name if more of it follows, else it ends a sentence
+        let continues = match rest.strip_prefix('.') {
+            Some(after) => after.starts_with(is_name_char),
+            None => rest.starts_with(is_name_char),
+        };
+        !text[..start].ends_with(is_name_char) && !continues
+    })
+}
+
+impl Feed {
+    /// Load the feed from `file`, or fetch it from archlinux.org
+    pub fn load(file: Option<&Path>) -> Result<Self> {
+        let path = match file {
+            Some(file) => file.to_owned(),
+            None => download::fetch_cached(FEED_URL, "news.xml", MAX_CACHE_AGE)?,
+        };
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid news feed in {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let doc = roxmltree::Document::parse(contents)?;
+        let items = doc
+            .descendants()
+            .filter(|node| node.has_tag_name("item"))
+            .map(|item| {
+                let child = |name: &str| {
+                    item.children()
+                        .find(|node| node.has_tag_name(name))
+                        .and_then(|node| node.text())
+                        .unwrap_or_default()
+                        .trim()
+                        .to_owned()
+                };
+                let (title, link, guid) = (child("title"), child("link"), child("guid"));
+                Item {
+                    id: if guid.is_empty() { link.clone() } else { guid },
+                    published: parse_date(&child("pubDate")),
+                    text: regex!(r"<[^>]*>").replace_all(&child("description"), " ").into_owned(),
+                    title,
+                    link,
+                    mentions: Vec::new(),
+                }
+            })
+            .collect();
+        Ok(Self { items })
+    }
+
+    /// The items published after `since` which haven't been acknowledged, with the pending
+    /// packages they mention. Without `since` every unacknowledged item counts.
+    pub fn unread(&self, since: Option<u64>, upgrades: &[Upgrade]) -> Result<Vec<Item>> {
+        let read = ReadItems::load()?;
+        let mut pkgnames: Vec<&str> = upgrades.iter().map(|u| &*u.pkgname).collect();
+        pkgnames.sort_unstable();
+        pkgnames.dedup();
+        Ok(self
+            .items
+            .iter()
+            .filter(|item| !read.read.contains(&item.id))
+            .filter(|item| match (since, item.published) {
+                (Some(since), Some(published)) => published > since,
+                _ => true,
+            })
+            .map(|item| {
+                let mentions = pkgnames
+                    .iter()
+                    .filter(|p| mentions(&item.title, p) || mentions(&item.text, p))
+                    .map(|p| p.to_string())
+                    .collect();
+                Item { mentions, ..item.clone() }
+            })
+            .collect())
+    }
+
+    /// Record `items` as read. Items which dropped out of the feed are forgotten, since they
+    /// can't come back.
+    pub fn acknowledge(&self, items: &[Item]) -> Result<()> {
+        let mut read = ReadItems::load()?;
+        read.read.retain(|id| self.items.iter().any(|item| item.id == *id));
+        read.read.extend(items.iter().map(|item| item.id.clone()));
+        crate::write_atomic(&ReadItems::path(), serde_json::to_string(&read)?.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        let cases = [
+            ("Sat, 05 Oct 2024 10:00:00 +0000", Some(1728122400)),
+            ("Sat, 05 Oct 2024 12:00:00 +0200", Some(1728122400)),
+            ("Sat, 05 Oct 2024 05:00:00 -0500", Some(1728122400)),
+            ("5 Oct 2024 10:00 GMT", Some(1728122400)),
+            ("Thu, 29 Feb 2024 23:59:59 Z", Some(1709251199)),
+            ("2024-10-05T10:00:00Z", None),
+            ("Sat, 05 Foo 2024 10:00:00 +0000", None),
+            ("", None),
+        ];
+        for (date, secs) in cases {
+            assert_eq!(parse_date(date), secs, "{date}");
+        }
+    }
+
+    #[test]
+    fn package_mentions() {
+        let text = "Upgrading glibc and lib32-glibc, vim-plugins, (python) or linux.";
+        for pkgname in ["glibc", "lib32-glibc", "vim-plugins", "python", "linux"] {
+            assert!(mentions(text, pkgname), "{pkgname}");
+        }
+        for pkgname in ["lib32", "vim", "plugins", "gcc"] {
+            assert!(!mentions(text, pkgname), "{pkgname}");
+        }
+    }
+
+    #[test]
+    fn parse_feed() {
+        let feed = Feed::parse(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+            <rss version="2.0"><channel><title>Arch Linux: Recent news updates</title>
+            <item>
+              <title>glibc 2.41 needs manual intervention</title>
+              <link>https://archlinux.org/news/glibc-241/</link>
+              <description>
+                &lt;p&gt;Upgrading &lt;code&gt;glibc&lt;/code&gt; and lib32-glibc&lt;/p&gt;
+              </description>
+              <pubDate>Sat, 05 Oct 2024 10:00:00 +0000</pubDate>
+              <guid isPermaLink="false">tag:archlinux.org,2024-10-05:/news/glibc-241/</guid>
+            </item>
+            <item>
+              <title>No guid</title>
+              <link>https://archlinux.org/news/no-guid/</link>
+              <pubDate>not a date</pubDate>
+            </item>
+            </channel></rss>"#,
+        )
+        .unwrap();
+        let [glibc, no_guid] = &feed.items[..] else { panic!("{:?}", feed.items) };
+        assert_eq!(glibc.title, "glibc 2.41 needs manual intervention");
+        assert_eq!(glibc.id, "tag:archlinux.org,2024-10-05:/news/glibc-241/");
+        assert_eq!(glibc.published, Some(1728122400));
+        assert!(mentions(&glibc.text, "glibc") && mentions(&glibc.text, "lib32-glibc"));
+        assert!(!glibc.text.contains('<'));
+        assert_eq!(no_guid.id, "https://archlinux.org/news/no-guid/");
+        assert_eq!(no_guid.published, None);
+        assert!(Feed::parse("<rss><channel>").is_err());
+    }
+}
@@ -0,0 +1,872 @@
+//! Formatting for the text output: the colorized list of upgrades and the summary
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+
+use ahash::HashMap;
+use owo_colors::OwoColorize;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+use clap::builder::PossibleValue;
+
+use crate::size::SizeFormat;
+use crate::{cooldown, history, news, pacman_log, security, theme, version, Repo, Totals, Upgrade};
+
+/// Versions aren't elided below this width, the two line layout is used instead
+const MIN_VERSION_WIDTH: usize = 12;
+/// How many CVE IDs are listed for an upgrade, the rest are counted
+const MAX_CVES_SHOWN: usize = 3;
+/// Indent of the versions in the two line layout
+const TWO_LINE_INDENT: usize = 4;
+
+/// Optional columns in the list of upgrades. The class is shown before the versions, and the
+/// others after.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Class,
+    Download,
+    Installed,
+    Net,
+    LastUpgrade,
+}
+
+impl clap::ValueEnum for Column {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Class, Self::Download, Self::Installed, Self::Net, Self::LastUpgrade]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Class => PossibleValue::new("class").help("Kind of version change"),
+            Self::Download => PossibleValue::new("download").help("Download size"),
+            Self::Installed => PossibleValue::new("installed").help("Installed size"),
+            Self::Net => PossibleValue::new("net").help("Change in installed size"),
+            Self::LastUpgrade => PossibleValue::new("last-upgrade")
+                .help("When the package was last upgraded, from pacman's log"),
+        })
+    }
+}
+
+impl Column {
+    /// The columns after the versions, in the order they're displayed
+    const TRAILING: [Self; 4] = [Self::Download, Self::Installed, Self::Net, Self::LastUpgrade];
+
+    fn header(self) -> &'static str {
+        match self {
+            Self::Class => "Class",
+            Self::Download => "Download",
+            Self::Installed => "Installed",
+            Self::Net => "Net",
+            Self::LastUpgrade => "Upgraded",
+        }
+    }
+
+    /// Format this trailing column for a row of upgrades
+    fn format_value(self, upgrades: &[&Upgrade], sizes: SizeFormat) -> String {
+        match self {
+            Self::Class => unreachable!("class isn't a trailing column"),
+            Self::Download => sizes.format(upgrades.iter().map(|u| u.download_size).sum()),
+            Self::Installed => sizes.format(upgrades.iter().map(|u| u.install_size).sum()),
+            Self::Net => sizes.format_signed(upgrades.iter().map(|u| u.net_size()).sum()),
+            // split packages are upgraded together, so the first one is enough
+            Self::LastUpgrade => {
+                upgrades[0].last_upgrade.map_or("-".into(), pacman_log::format_date)
+            }
+        }
+    }
+}
+
+/// Options controlling the text output
+#[derive(Debug, Default, Clone)]
+pub struct TextOptions {
+    /// Extra columns to show
+    pub columns: Vec<Column>,
+    /// How to show sizes, in the summary and columns
+    pub sizes: SizeFormat,
+    /// Collapse split packages built from the same pkgbase into one line
+    pub group: bool,
+    /// With `group`, list the packages in each group below it
+    pub expand_groups: bool,
+    /// Show a section for each repo, with subtotals
+    pub by_repo: bool,
+    /// Maximum width of a line, or `None` for no limit
+    pub width: Option<usize>,
+}
+
+impl TextOptions {
+    fn has_column(&self, column: Column) -> bool {
+        self.columns.contains(&column)
+    }
+
+    /// The columns to show after the versions, in display order
+    fn trailing_columns(&self) -> impl Iterator<Item = Column> + '_ {
+        Column::TRAILING.into_iter().filter(|c| self.has_column(*c))
+    }
+}
+
+/// The number of terminal columns used by `s`, 2 for wide characters like CJK ideographs
+pub fn text_width(s: &str) -> usize {
+    s.width()
+}
+
+/// `s` padded with spaces to `width` columns, since `{:width$}` counts characters
+fn pad(s: &str, width: usize) -> String {
+    format!("{s}{:w$}", "", w = width.saturating_sub(text_width(s)))
+}
+
+/// The leading `chars` which fit into `max` columns
+fn take_width(chars: impl Iterator<Item = char>, max: usize) -> impl Iterator<Item = char> {
+    let mut width = 0;
+    chars.take_while(move |c| {
+        width += c.width().unwrap_or(0);
+        width <= max
+    })
+}
+
+/// The width of the terminal on stdout, from `$COLUMNS` or the tty. `None` if stdout isn't a
+/// terminal, so piped output is never truncated.
+pub fn terminal_width() -> Option<usize> {
+    let stdout = io::stdout();
+    if !stdout.is_terminal() {
+        return None;
+    }
+    if let Some(columns) = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()) {
+        return Some(columns);
+    }
+    match rustix::termios::tcgetwinsize(stdout) {
+        Ok(size) if size.ws_col > 0 => Some(size.ws_col.into()),
+        _ => None,
+    }
+}
+
+/// Shorten `s` to at most `max` columns by cutting off its end, marked with `…`
+fn truncate(s: &str, max: usize) -> Cow<'_, str> {
+    if text_width(s) <= max {
+        return Cow::Borrowed(s);
+    }
+    let keep = max.saturating_sub(1);
+    Cow::Owned(take_width(s.chars(), keep).chain(['…']).collect())
+}
+
+/// Shorten `s` to at most `max` columns by replacing its middle with `…`. Both ends are kept
+/// since for versions the start is the most significant part, and the end holds the pkgrel. A
+/// wide character which doesn't fit leaves the result a column short.
+fn elide(s: &str, max: usize) -> Cow<'_, str> {
+    if text_width(s) <= max {
+        return Cow::Borrowed(s);
+    }
+    let keep = max.saturating_sub(1);
+    let head: String = take_width(s.chars(), keep - keep / 2).collect();
+    let mut tail: Vec<char> = take_width(s.chars().rev(), keep - text_width(&head)).collect();
+    tail.reverse();
+    Cow::Owned(head.chars().chain(['…']).chain(tail).collect())
+}
+
+/// One line of the upgrade list: either a single upgrade, or a group of split packages from the
+/// same pkgbase which are upgraded between the same versions.
+#[derive(Debug)]
+struct Row<'a> {
+    /// The pkgname, or pkgbase for groups
+    name: Cow<'a, str>,
+    /// Never empty, the first upgrade provides the repo and versions for the whole row
+    upgrades: Vec<&'a Upgrade>,
+}
+
+impl<'a> Row<'a> {
+    fn first(&self) -> &'a Upgrade {
+        self.upgrades[0]
+    }
+
+    fn is_group(&self) -> bool {
+        self.upgrades.len() > 1
+    }
+}
+
+/// Build the rows for a list of upgrades, keeping their order. When `group` is set, upgrades with
+/// the same repo, pkgbase, and versions are merged into the row of the first one.
+fn make_rows<'a>(upgrades: impl IntoIterator<Item = &'a Upgrade>, group: bool) -> Vec<Row<'a>> {
+    type Key<'a> = (Option<&'a Repo>, &'a str, &'a str, &'a str);
+    let mut rows: Vec<Row<'a>> = Vec::new();
+    let mut groups: HashMap<Key<'a>, usize> = HashMap::default();
+
+    for u in upgrades {
+        if let (true, Some(pkgbase)) = (group, &u.pkgbase) {
+            let key = (u.repo.as_ref(), &**pkgbase, &*u.oldver, &*u.newver);
+            if let Some(&idx) = groups.get(&key) {
+                rows[idx].upgrades.push(u);
+                rows[idx].name = Cow::Borrowed(pkgbase);
+                continue;
+            }
+            groups.insert(key, rows.len());
+        }
+        rows.push(Row { name: Cow::Borrowed(&u.pkgname), upgrades: vec![u] });
+    }
+    rows
+}
+
+/// Column widths for the list of upgrades
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Widths {
+    /// whether rows start with "repo/"
+    show_repo: bool,
+    /// the max length of "repo/pkgname", or just pkgname
+    repo_name: usize,
+    /// width of the version class column, 0 if it's hidden
+    class: usize,
+    oldver: usize,
+    newver: usize,
+    /// width of each trailing column that's shown, after the versions
+    trailing: usize,
+    /// the number of trailing columns
+    trailing_columns: usize,
+    /// put the versions on a second line, below the name
+    two_line: bool,
+}
+
+impl Widths {
+    fn of<'a>(
+        rows: impl IntoIterator<Item = &'a Row<'a>>,
+        show_repo: bool,
+        opts: &TextOptions,
+    ) -> Self {
+        let class = if opts.has_column(Column::Class) { "rebuild".len() } else { 0 };
+        let trailing = opts.trailing_columns().map(|c| c.header().len()).max().unwrap_or(0);
+        let init = Self {
+            show_repo,
+            repo_name: 0,
+            class,
+            oldver: 0,
+            newver: 0,
+            trailing,
+            trailing_columns: opts.trailing_columns().count(),
+            two_line: false,
+        };
+        rows.into_iter().fold(init, |w, row| {
+            let u = row.first();
+            let repo_width = match &u.repo {
+                // add 1 for the '/' after the repo name
+                Some(repo) if show_repo => text_width(repo.as_str()) + 1,
+                _ => 0,
+            };
+            let trailing = opts
+                .trailing_columns()
+                .map(|c| c.format_value(&row.upgrades, opts.sizes).len())
+                .fold(w.trailing, usize::max);
+            Self {
+                repo_name: w.repo_name.max(repo_width + text_width(&row.name)),
+                oldver: w.oldver.max(text_width(&u.oldver)),
+                newver: w.newver.max(text_width(&u.newver)),
+                trailing,
+                ..w
+            }
+        })
+    }
+
+    /// Width of the class column including its separator, or 0 if it's hidden
+    fn class_part(&self) -> usize {
+        if self.class > 0 {
+            self.class + 2
+        } else {
+            0
+        }
+    }
+
+    /// Width of all the trailing columns including their separators
+    fn trailing_part(&self) -> usize {
+        self.trailing_columns * (self.trailing + 2)
+    }
+
+    /// Width of "old -> new"
+    fn versions(&self) -> usize {
+        self.oldver + " -> ".len() + self.newver
+    }
+
+    /// Width of a line, not counting notes like `[cached]` after the columns
+    fn line(&self) -> usize {
+        if self.two_line {
+            let first = self.repo_name + self.class_part() + self.trailing_part();
+            first.max(TWO_LINE_INDENT + self.versions())
+        } else {
+            self.repo_name + self.class_part() + 2 + self.versions() + self.trailing_part()
+        }
+    }
+
+    /// Shrink the columns to fit lines into `width`. Long versions are elided first, and if that
+    /// isn't enough the versions move to a second line. Names are truncated as a last resort.
+    fn fit(self, width: Option<usize>) -> Self {
+        let Some(width) = width else {
+            return self;
+        };
+        if self.line() <= width {
+            return self;
+        }
+
+        let fixed = self.repo_name + self.class_part() + 2 + " -> ".len() + self.trailing_part();
+        let available = width.saturating_sub(fixed);
+        if let Some(fitted) = self.cap_versions(available) {
+            return fitted;
+        }
+
+        let mut two_line = Self { two_line: true, ..self };
+        let available = width.saturating_sub(TWO_LINE_INDENT + " -> ".len());
+        two_line = two_line.cap_versions(available).unwrap_or(Self {
+            oldver: two_line.oldver.min(MIN_VERSION_WIDTH),
+            newver: two_line.newver.min(MIN_VERSION_WIDTH),
+            ..two_line
+        });
+        let name_width = width.saturating_sub(two_line.class_part() + two_line.trailing_part());
+        two_line.repo_name = two_line.repo_name.min(name_width.max(MIN_VERSION_WIDTH));
+        two_line
+    }
+
+    /// Elide the versions so that both fit into `available` columns, sharing it evenly unless one
+    /// of them is shorter. `None` if that would elide them below `MIN_VERSION_WIDTH`.
+    fn cap_versions(self, available: usize) -> Option<Self> {
+        let (short, long) = (self.oldver.min(self.newver), self.oldver.max(self.newver));
+        let cap = if short + long <= available {
+            long
+        } else if short * 2 <= available {
+            available - short
+        } else {
+            available / 2
+        };
+        (cap >= MIN_VERSION_WIDTH).then(|| Self {
+            oldver: self.oldver.min(cap),
+            newver: self.newver.min(cap),
+            ..self
+        })
+    }
+}
+
+/// Write the header line for the size columns, if any are shown
+fn write_header(out: &mut impl Write, widths: Widths, opts: &TextOptions) -> io::Result<()> {
+    let theme = theme::get();
+    if widths.trailing == 0 {
+        return Ok(());
+    }
+    let mut left = widths.repo_name + widths.class_part();
+    if !widths.two_line {
+        left += 2 + widths.versions();
+    }
+    write!(out, "{:left$}", "")?;
+    for column in opts.trailing_columns() {
+        write!(out, "  {:>w$}", column.header().style(theme.header), w = widths.trailing)?;
+    }
+    writeln!(out)
+}
+
+/// The note for the security issues fixed by a row, like `[High: CVE-2024-0001, CVE-2024-0002]`
+fn security_note(upgrades: &[&Upgrade]) -> Option<String> {
+    let fixes: Vec<&security::Fix> = upgrades.iter().flat_map(|u| u.security.iter()).collect();
+    let severity = fixes.iter().map(|f| f.severity).max()?;
+    let mut ids: Vec<&str> = Vec::new();
+    for fix in fixes.iter() {
+        // groups without CVEs yet are named by their group ID
+        let fix_ids =
+            if fix.cves.is_empty() { std::slice::from_ref(&fix.group) } else { &fix.cves };
+        for id in fix_ids {
+            if !ids.contains(&id.as_str()) {
+                ids.push(id);
+            }
+        }
+    }
+    let mut note = format!("[{severity}: {}", ids[..ids.len().min(MAX_CVES_SHOWN)].join(", "));
+    if ids.len() > MAX_CVES_SHOWN {
+        note += &format!(" +{} more", ids.len() - MAX_CVES_SHOWN);
+    }
+    note.push(']');
+    Some(note)
+}
+
+/// Write one row of the upgrade list, followed by `note` (which may be empty).
+fn write_row(
+    out: &mut impl Write,
+    row: &Row,
+    widths: Widths,
+    opts: &TextOptions,
+    note: &str,
+) -> io::Result<()> {
+    let theme = theme::get();
+    let u = row.first();
+    let repo_name_width = widths.repo_name;
+    let repo_width = match &u.repo {
+        Some(repo) if widths.show_repo => {
+            write!(out, "{}/", repo.style(theme.repo(repo)))?;
+            text_width(repo.as_str()) + 1
+        }
+        _ => 0,
+    };
+    let name = truncate(&row.name, repo_name_width.saturating_sub(repo_width).max(1));
+    write!(out, "{name}")?;
+    // nothing follows the name on its line in the two line layout without extra columns
+    if !widths.two_line || widths.class > 0 || widths.trailing > 0 {
+        write!(
+            out,
+            "{:w$}",
+            "",
+            w = repo_name_width.saturating_sub(repo_width + text_width(&name))
+        )?;
+    }
+    if widths.class > 0 {
+        write!(out, "  {:w$}", u.class.style(theme.note), w = widths.class)?;
+    }
+
+    let oldver = elide(&u.oldver, widths.oldver);
+    let newver = elide(&u.newver, widths.newver);
+    let clen = version::common_prefix_len(&oldver, &newver);
+    let versions = format!(
+        "{ocommon}{ounique}{space:width$} -> {ncommon}{nunique}",
+        ocommon = (&oldver[..clen]).style(theme.common),
+        ounique = (&oldver[clen..]).style(theme.old),
+        space = "",
+        width = widths.oldver - text_width(&oldver),
+        ncommon = (&newver[..clen]).style(theme.common),
+        nunique = (&newver[clen..]).style(theme.new),
+    );
+    if !widths.two_line {
+        write!(out, "  {versions}")?;
+        if widths.trailing > 0 {
+            write!(out, "{:w$}", "", w = widths.newver - text_width(&newver))?;
+        }
+    }
+    if widths.trailing > 0 {
+        for column in opts.trailing_columns() {
+            let size = column.format_value(&row.upgrades, opts.sizes);
+            write!(out, "  {size:>w$}", w = widths.trailing)?;
+        }
+    }
+    if row.upgrades.iter().all(|u| u.cached) {
+        write!(out, " {}", "[cached]".style(theme.note))?;
+    }
+    if row.upgrades.iter().any(|u| u.new) {
+        write!(out, " {}", "[new]".style(theme.new).bold())?;
+    }
+    if let Some(security) = security_note(&row.upgrades) {
+        write!(out, " {}", security.style(theme.security))?;
+    }
+    if row.is_group() {
+        let size = Column::Download.format_value(&row.upgrades, opts.sizes);
+        let group_note = format!(" ({} packages, {size})", row.upgrades.len());
+        write!(out, "{}", group_note.style(theme.note))?;
+    }
+    if !note.is_empty() {
+        write!(out, "{}", note.style(theme.note))?;
+    }
+    writeln!(out)?;
+    if widths.two_line {
+        writeln!(out, "{:TWO_LINE_INDENT$}{versions}", "")?;
+    }
+
+    if row.is_group() && opts.expand_groups {
+        let names: Vec<&str> = row.upgrades.iter().map(|u| &*u.pkgname).collect();
+        writeln!(out, "    {}", names.join(", "))?;
+    }
+    Ok(())
+}
+
+/// Write the list of upgrades which are ready, and then those which are still cooling down at
+/// time `now`.
+pub fn write_list(
+    out: &mut impl Write,
+    upgrades: &[Upgrade],
+    cooling: &[Upgrade],
+    now: u64,
+    opts: &TextOptions,
+) -> io::Result<()> {
+    let theme = theme::get();
+    let cooling_rows = make_rows(cooling, opts.group);
+
+    if opts.by_repo {
+        // split into sections, keeping the sort order within each repo
+        let mut sections: BTreeMap<&Repo, Vec<&Upgrade>> = BTreeMap::new();
+        for u in upgrades {
+            sections.entry(u.repo_or_unknown()).or_default().push(u);
+        }
+        let sections: Vec<(&Repo, Vec<Row>)> = sections
+            .into_iter()
+            .map(|(repo, upgrades)| (repo, make_rows(upgrades, opts.group)))
+            .collect();
+        let widths =
+            Widths::of(sections.iter().flat_map(|(_, rows)| rows), false, opts).fit(opts.width);
+        write_header(out, widths, opts)?;
+
+        for (i, (repo, rows)) in sections.iter().enumerate() {
+            if i > 0 {
+                writeln!(out)?;
+            }
+            writeln!(out, "{}", repo.style(theme.repo(repo)).bold())?;
+            for row in rows {
+                write_row(out, row, widths, opts, "")?;
+            }
+            let totals = Totals::of(rows.iter().flat_map(|row| row.upgrades.iter().copied()));
+            let subtotal = format!(
+                "{} packages, {} download, {} net",
+                totals.packages,
+                opts.sizes.format(totals.download_size),
+                opts.sizes.format_signed(totals.net_size),
+            );
+            writeln!(out, "{}", subtotal.style(theme.note))?;
+        }
+    } else {
+        let ready_rows = make_rows(upgrades, opts.group);
+        let widths =
+            Widths::of(ready_rows.iter().chain(cooling_rows.iter()), true, opts).fit(opts.width);
+        write_header(out, widths, opts)?;
+        for row in ready_rows.iter() {
+            write_row(out, row, widths, opts, "")?;
+        }
+    }
+
+    if !cooling_rows.is_empty() {
+        let widths = Widths::of(cooling_rows.iter(), true, opts).fit(opts.width);
+        writeln!(out)?;
+        writeln!(out, "Cooling down:")?;
+        for row in cooling_rows.iter() {
+            // upgrades are only cooling down when ready_at is set and in the future
+            let wait = row.first().ready_at.unwrap() - now;
+            let note = format!(" (ready in {})", cooldown::describe_wait(wait));
+            write_row(out, row, widths, opts, &note)?;
+        }
+    }
+    Ok(())
+}
+
+/// Write the upgrades from the previous run, `age` seconds ago, which aren't pending any more
+pub fn write_disappeared(
+    out: &mut impl Write,
+    disappeared: &[history::Disappeared],
+    age: u64,
+) -> io::Result<()> {
+    let theme = theme::get();
+    writeln!(out, "No longer pending since the last run ({}):", cooldown::describe_elapsed(age))?;
+    // like the upgrade list, only known repos are shown
+    let repos: Vec<Option<Repo>> =
+        disappeared.iter().map(|d| Some(d.entry.repo()).filter(|r| *r != Repo::Unknown)).collect();
+    let repo_name_width = |repo: &Option<Repo>, pkgname: &str| {
+        repo.as_ref().map_or(0, |r| text_width(r.as_str()) + 1) + text_width(pkgname)
+    };
+    let name_width = disappeared
+        .iter()
+        .zip(repos.iter())
+        .map(|(d, repo)| repo_name_width(repo, &d.entry.pkgname))
+        .max()
+        .unwrap_or(0);
+    let oldver_width = disappeared.iter().map(|d| text_width(&d.entry.oldver)).max().unwrap_or(0);
+    let newver_width = disappeared.iter().map(|d| text_width(&d.entry.newver)).max().unwrap_or(0);
+    for (d, repo) in disappeared.iter().zip(repos.iter()) {
+        if let Some(repo) = repo {
+            write!(out, "{}/", repo.style(theme.repo(repo)))?;
+        }
+        writeln!(
+            out,
+            "{name}{space:pad$}  {oldver} -> {newver}  {reason}",
+            name = d.entry.pkgname,
+            space = "",
+            pad = name_width - repo_name_width(repo, &d.entry.pkgname),
+            oldver = pad(&d.entry.oldver, oldver_width),
+            newver = pad(&d.entry.newver, newver_width),
+            reason = d.reason.as_str().style(theme.note),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write a compact table of package count, download size, and net size for each repo
+pub fn write_repo_table(
+    out: &mut impl Write,
+    by_repo: &BTreeMap<&Repo, Totals>,
+    sizes: SizeFormat,
+) -> io::Result<()> {
+    let theme = theme::get();
+    let repo_width = by_repo.keys().map(|r| text_width(r.as_str())).max().unwrap_or(0).max(4);
+    let w = SizeFormat::WIDTH;
+    writeln!(out, "{:repo_width$}  {:>5}  {:>w$}  {:>w$}", "Repo", "Pkgs", "Download", "Net")?;
+    for (repo, totals) in by_repo {
+        writeln!(
+            out,
+            "{repo}{space:pad$}  {:5}  {:>w$}  {:>w$}",
+            totals.packages,
+            sizes.format(totals.download_size),
+            sizes.format_signed(totals.net_size),
+            repo = repo.style(theme.repo(repo)),
+            space = "",
+            pad = repo_width - text_width(repo.as_str()),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write the summary of the upgrades to install, and how many are held back for cooldown
+pub fn write_summary(
+    out: &mut impl Write,
+    totals: &Totals,
+    cooling: usize,
+    sizes: SizeFormat,
+) -> io::Result<()> {
+    let theme = theme::get();
+    let w = SizeFormat::WIDTH;
+    writeln!(out, "Packages to upgrade:  {:5}", totals.packages.style(theme.totals))?;
+    if cooling > 0 {
+        writeln!(out, "Cooling down:         {:5}", cooling.style(theme.totals))?;
+    }
+    if totals.security > 0 {
+        writeln!(out, "Security fixes:       {:5}", totals.security.style(theme.security))?;
+    }
+    if !totals.by_class.is_empty() {
+        let classes: Vec<String> =
+            totals.by_class.iter().map(|(class, n)| format!("{n} {class}")).collect();
+        writeln!(out, "Version changes:      {}", classes.join(", "))?;
+    }
+    writeln!(
+        out,
+        "Total download size:  {:>w$}",
+        sizes.format(totals.download_size).style(theme.totals)
+    )?;
+    writeln!(
+        out,
+        "  Already cached:     {:>w$}",
+        sizes.format(totals.cached_size).style(theme.totals)
+    )?;
+    writeln!(
+        out,
+        "  Still to download:  {:>w$}",
+        sizes.format(totals.download_size - totals.cached_size).style(theme.totals)
+    )?;
+    writeln!(
+        out,
+        "Total installed size: {:>w$}",
+        sizes.format(totals.install_size).style(theme.totals)
+    )?;
+    writeln!(
+        out,
+        "Net upgrade size:     {:>w$}",
+        sizes.format_signed(totals.net_size).style(theme.totals)
+    )?;
+    Ok(())
+}
+
+/// Write the unread news items, highlighting the ones which mention pending packages
+pub fn write_news(out: &mut impl Write, items: &[news::Item]) -> io::Result<()> {
+    let theme = theme::get();
+    writeln!(out, "{}", "Unread news since the last upgrade:".style(theme.header))?;
+    for item in items {
+        let date = item.published.map_or_else(|| "?".repeat(10), pacman_log::format_date);
+        let title = if item.mentions.is_empty() { owo_colors::Style::new() } else { theme.warning };
+        write!(out, "  {}  {}", date.style(theme.note), item.title.style(title))?;
+        if !item.mentions.is_empty() {
+            write!(
+                out,
+                "  {}",
+                format!("[mentions {}]", item.mentions.join(", ")).style(theme.note)
+            )?;
+        }
+        writeln!(out)?;
+        writeln!(out, "              {}", item.link)?;
+    }
+    Ok(())
+}
+
+/// Write what pacman's log says: when the last full system upgrade was, and which pending
+/// packages were downgraded in the past
+pub fn write_log_report(out: &mut impl Write, log: &pacman_log::Log, now: u64) -> io::Result<()> {
+    let theme = theme::get();
+    match log.last_full_upgrade {
+        Some(time) => writeln!(
+            out,
+            "Last full upgrade: {} ({})",
+            cooldown::describe_elapsed(now.saturating_sub(time)).style(theme.totals),
+            pacman_log::format_date(time),
+        )?,
+        None => writeln!(out, "Last full upgrade: {}", "not found in the log".style(theme.note))?,
+    }
+
+    let mut changes: Vec<(&str, &pacman_log::Change)> = log
+        .downgrades
+        .iter()
+        .flat_map(|(pkgname, changes)| changes.iter().map(move |c| (&**pkgname, c)))
+        .collect();
+    changes.sort_by_key(|(pkgname, c)| (*pkgname, c.time));
+    if changes.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "{}", "Pending packages downgraded before:".style(theme.header))?;
+    let name_width = changes.iter().map(|(p, _)| text_width(p)).max().unwrap_or(0);
+    let from_width = changes.iter().map(|(_, c)| text_width(&c.from)).max().unwrap_or(0);
+    let to_width = changes.iter().map(|(_, c)| text_width(&c.to)).max().unwrap_or(0);
+    for (pkgname, c) in changes {
+        writeln!(
+            out,
+            "  {pkgname}  {from} -> {to}  {date}",
+            pkgname = pad(pkgname, name_width),
+            from = pad(&c.from, from_width),
+            to = pad(&c.to, to_width),
+            date = pacman_log::format_date(c.time).style(theme.note),
+        )?;
+    }
+    Ok(())
+}
+
+/// Write how many packages have their installed version cached, and list the ones that don't,
+/// from the cached files found for each pkgname in `rollback`
+pub fn write_rollback(
+    out: &mut impl Write,
+    upgrades: &[Upgrade],
+    rollback: &BTreeMap<&str, Option<PathBuf>>,
+) -> io::Result<()> {
+    let theme = theme::get();
+    let missing: Vec<&Upgrade> = upgrades
+        .iter()
+        .filter(|u| rollback.get(&*u.pkgname).is_some_and(Option::is_none))
+        .collect();
+    writeln!(
+        out,
+        "Rollback: installed versions of {} of {} packages are cached",
+        (upgrades.len() - missing.len()).style(theme.totals),
+        upgrades.len().style(theme.totals),
+    )?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    writeln!(out, "{}", "No cached copy of the installed version:".style(theme.warning))?;
+    let name_width = missing.iter().map(|u| text_width(&u.pkgname)).max().unwrap_or(0);
+    for u in missing {
+        writeln!(out, "  {}  {}", pad(&u.pkgname, name_width), u.oldver.style(theme.old))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upgrades(lines: &[&str]) -> Vec<Upgrade> {
+        lines.iter().map(|line| line.parse().unwrap()).collect()
+    }
+
+    fn widths(upgrades: &[Upgrade]) -> Widths {
+        Widths::of(&make_rows(upgrades, false), false, &TextOptions::default())
+    }
+
+    #[test]
+    fn group_split_packages() {
+        let split = |line: &str, repo: &str, pkgbase: Option<&str>| {
+            let mut u: Upgrade = line.parse().unwrap();
+            u.repo = Some(Repo::from(repo));
+            u.pkgbase = pkgbase.map(str::to_owned);
+            u
+        };
+        let upgrades = [
+            split("python-foo 1.0-1 -> 1.1-1", "extra", Some("python-foo")),
+            split("gcc 13-1 -> 14-1", "core", None),
+            split("python-foo-docs 1.0-1 -> 1.1-1", "extra", Some("python-foo")),
+            // not built from the same upgrade of the pkgbase
+            split("python-foo-tests 1.0-1 -> 1.1-2", "extra", Some("python-foo")),
+            split("python-foo 1.0-1 -> 1.1-1", "extra-testing", Some("python-foo")),
+            split("gcc-libs 13-1 -> 14-1", "core", Some("gcc")),
+            split("lib32-gcc-libs 13-1 -> 14-1", "multilib", Some("gcc")),
+            split("gcc-fortran 13-1 -> 14-1", "core", Some("gcc")),
+        ];
+        let rows = |group| -> Vec<(String, Vec<&str>)> {
+            make_rows(&upgrades, group)
+                .iter()
+                .map(|row| {
+                    (row.name.to_string(), row.upgrades.iter().map(|u| &*u.pkgname).collect())
+                })
+                .collect()
+        };
+        let row = |name: &str, pkgnames: &[&'static str]| (name.to_owned(), pkgnames.to_vec());
+        assert_eq!(
+            rows(true),
+            [
+                row("python-foo", &["python-foo", "python-foo-docs"]),
+                row("gcc", &["gcc"]),
+                row("python-foo-tests", &["python-foo-tests"]),
+                row("python-foo", &["python-foo"]),
+                row("gcc", &["gcc-libs", "gcc-fortran"]),
+                row("lib32-gcc-libs", &["lib32-gcc-libs"]),
+            ]
+        );
+        assert_eq!(rows(false).len(), upgrades.len());
+        assert!(rows(false).iter().all(|(name, pkgnames)| pkgnames == &[&**name]));
+    }
+
+    #[test]
+    fn widths_of_text() {
+        assert_eq!(text_width("linux"), 5);
+        assert_eq!(text_width("pythön"), 6);
+        assert_eq!(text_width("日本語"), 6);
+        assert_eq!(pad("日本", 6), "日本  ");
+    }
+
+    #[test]
+    fn truncate_and_elide() {
+        let cases = [
+            ("1.2.3-1", 7, "1.2.3-1", "1.2.3-1"),
+            ("1.2.3.r456.g0123456-1", 9, "1.2.3.r4…", "1.2.…56-1"),
+            ("1.2.3.r456.g0123456-1", 1, "…", "…"),
+            ("ééééé-1", 5, "éééé…", "éé…-1"),
+            // wide characters which don't fit leave it a column short
+            ("日本語入力", 6, "日本…", "日…力"),
+        ];
+        for (s, max, truncated, elided) in cases {
+            assert_eq!(truncate(s, max), truncated, "truncate {s} {max}");
+            assert_eq!(elide(s, max), elided, "elide {s} {max}");
+            assert!(text_width(&elide(s, max)) <= max, "elide {s} {max}");
+        }
+    }
+
+    #[test]
+    fn fit_without_limit() {
+        let w = widths(&upgrades(&[
+            "linux 6.7.3.arch1-1 -> 6.7.4.arch1-1",
+            "firefox 122.0-1 -> 122.0.1-1",
+        ]));
+        assert_eq!((w.repo_name, w.oldver, w.newver, w.line()), (7, 13, 13, 39));
+        assert_eq!(w.fit(None), w);
+        assert_eq!(w.fit(Some(80)), w);
+        assert_eq!(w.fit(Some(39)), w);
+    }
+
+    #[test]
+    fn fit_by_eliding_versions() {
+        let w = widths(&upgrades(&["git-pkg 1.2.3.r456.g0123456-1 -> 1.2.3.r460.gfedcba9-1"]));
+        assert_eq!(w.line(), 55);
+        let fitted = w.fit(Some(50));
+        assert_eq!((fitted.two_line, fitted.oldver, fitted.newver), (false, 18, 18));
+        assert!(fitted.line() <= 50);
+
+        // the shorter version is kept whole, the longer one gets the rest
+        let w = widths(&upgrades(&["foo 1.2.3-1 -> 1.2.3.r460.gfedcba9-1"]));
+        let fitted = w.fit(Some(34));
+        assert_eq!((fitted.two_line, fitted.oldver, fitted.newver), (false, 7, 18));
+        assert_eq!(fitted.line(), 34);
+    }
+
+    #[test]
+    fn fit_on_two_lines() {
+        let w = widths(&upgrades(&["git-pkg 1.2.3.r456.g0123456-1 -> 1.2.3.r460.gfedcba9-1"]));
+        // room for the versions on their own line
+        let fitted = w.fit(Some(34));
+        assert_eq!((fitted.two_line, fitted.oldver, fitted.newver), (true, 13, 13));
+        assert_eq!(fitted.line(), 34);
+        // narrower than the name and arrow, the versions stop at the minimum
+        let fitted = w.fit(Some(10));
+        assert!(fitted.two_line);
+        assert_eq!((fitted.oldver, fitted.newver), (MIN_VERSION_WIDTH, MIN_VERSION_WIDTH));
+        assert_eq!(fitted.repo_name, 7);
+    }
+
+    #[test]
+    fn fit_long_names() {
+        let w = widths(&upgrades(&["a-very-long-package-name-indeed 1.0-1 -> 1.1-1"]));
+        let fitted = w.fit(Some(10));
+        assert!(fitted.two_line);
+        assert_eq!(fitted.repo_name, MIN_VERSION_WIDTH);
+        // wide characters count twice
+        let w = widths(&upgrades(&["日本語入力 1.0-1 -> 1.1-1"]));
+        assert_eq!(w.repo_name, 10);
+        assert_eq!(w.fit(Some(20)).repo_name, 10);
+    }
+}
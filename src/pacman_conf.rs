@@ -0,0 +1,69 @@
+//! Query pacman's configuration using the `pacman-conf` helper
+//!
+//! Parsing `pacman.conf` ourselves would mean handling `Include` directives, mirrorlists, and
+//! variable expansion in server URLs, so we let `pacman-conf` do the work instead.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+use bstr::ByteSlice;
+
+/// Run `pacman-conf` with the given arguments and return its output lines.
+///
+/// Any failure (pacman-conf missing, unknown option, etc.) results in an empty list, callers are
+/// expected to fall back to pacman's defaults.
+fn pacman_conf(args: &[&str]) -> Vec<Vec<u8>> {
+    match Command::new("pacman-conf").args(args).output() {
+        Ok(output) if output.status.success() => output
+            .stdout
+            .lines()
+            .map(|line| line.trim().to_owned())
+            .filter(|line| !line.is_empty())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The main pacman database directory. Normally this should just be "/var/lib/pacman/" but check
+/// pacman-conf in case it's set to something different somehow.
+pub fn db_path() -> PathBuf {
+    pacman_conf(&["DBPath"])
+        .into_iter()
+        .next()
+        .map(|dbpath| PathBuf::from(OsStr::from_bytes(&dbpath)))
+        .filter(|dbpath| dbpath.is_dir())
+        .unwrap_or_else(|| PathBuf::from("/var/lib/pacman/"))
+}
+
+/// The path of pacman's log file, defaulting to "/var/log/pacman.log".
+pub fn log_file() -> PathBuf {
+    pacman_conf(&["LogFile"])
+        .into_iter()
+        .next()
+        .map(|path| PathBuf::from(OsStr::from_bytes(&path)))
+        .unwrap_or_else(|| PathBuf::from("/var/log/pacman.log"))
+}
+
+/// All configured package cache directories, defaulting to "/var/cache/pacman/pkg/".
+pub fn cache_dirs() -> Vec<PathBuf> {
+    let dirs: Vec<PathBuf> = pacman_conf(&["CacheDir"])
+        .into_iter()
+        .map(|dir| PathBuf::from(OsStr::from_bytes(&dir)))
+        .collect();
+    if dirs.is_empty() {
+        vec![PathBuf::from("/var/cache/pacman/pkg/")]
+    } else {
+        dirs
+    }
+}
+
+/// The server URLs configured for `repo`, in order of preference, with `$repo` and `$arch`
+/// already expanded.
+pub fn repo_servers(repo: &str) -> Vec<String> {
+    pacman_conf(&["--repo", repo, "Server"])
+        .into_iter()
+        .filter_map(|url| String::from_utf8(url).ok())
+        .collect()
+}
@@ -0,0 +1,285 @@
+//! Parse pacman's log file to find what was done to packages in the past
+//!
+//! Lines look like `[2024-01-31T10:16:01+0100] [ALPM] upgraded glibc (2.38-1 -> 2.39-1)`. Logs
+//! written by pacman before 5.1 use local times like `[2018-05-01 10:16]`, which are treated as
+//! UTC since the offset isn't known.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use ahash::HashMap;
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::regex;
+
+/// A version change of a package recorded in the log
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub time: u64,
+    pub from: String,
+    pub to: String,
+}
+
+/// What the log tells about the packages we care about
+#[derive(Debug, Default, Serialize)]
+pub struct Log {
+    /// When each package was last installed, upgraded, or downgraded
+    pub last_changed: HashMap<String, u64>,
+    /// When the last full system upgrade (`pacman -Su`) completed. One with nothing to do looks
+    /// the same in the log as one declined at the prompt, so only completed transactions count.
+    pub last_full_upgrade: Option<u64>,
+    /// The downgrades of each package, oldest first
+    pub downgrades: HashMap<String, Vec<Change>>,
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // from Howard Hinnant's date algorithms
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (i64::from(month) + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Format seconds since the Unix epoch as a UTC date, `YYYY-MM-DD`
+pub fn format_date(secs: u64) -> String {
+    // the inverse of days_from_civil
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let doe = days.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Parse a log timestamp, without the brackets, into seconds since the Unix epoch
+fn parse_timestamp(s: &str) -> Option<u64> {
+    let re = regex!(
+        r"^(\d{4})-(\d{2})-(\d{2})[T ](\d{2}):(\d{2})(?::(\d{2}))?(?:([+-])(\d{2})(\d{2}))?$"
+    );
+    let caps = re.captures(s)?;
+    let num = |i: usize| caps.get(i).map_or(0, |m| m.as_str().parse::<i64>().unwrap());
+    let days = days_from_civil(num(1), num(2) as u32, num(3) as u32);
+    let mut secs = days * 86400 + num(4) * 3600 + num(5) * 60 + num(6);
+    let offset = num(8) * 3600 + num(9) * 60;
+    match caps.get(7).map(|m| m.as_str()) {
+        Some("+") => secs -= offset,
+        Some(_) => secs += offset,
+        None => {}
+    }
+    u64::try_from(secs).ok()
+}
+
+impl Log {
+    /// Read the log at `path`, keeping per-package details only for packages accepted by `filter`
+    pub fn read(path: &Path, filter: impl Fn(&str) -> bool) -> Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        Self::parse(BufReader::new(file), filter)
+            .with_context(|| format!("failed to read {}", path.display()))
+    }
+
+    /// Parse log lines, see `read`
+    pub fn parse(reader: impl BufRead, filter: impl Fn(&str) -> bool) -> Result<Self> {
+        let line_re = regex!(r"^\[([^\]]+)\] \[(ALPM|PACMAN)\] (.*)$");
+        let action_re = regex!(
+            r"^(installed|upgraded|downgraded|reinstalled|removed) (\S+) \((\S+)(?: -> (\S+))?\)$"
+        );
+
+        let mut log = Self::default();
+        // whether the current command is a full upgrade, until its transaction finishes
+        let mut full_upgrade = false;
+        // pacman.log isn't always valid UTF-8 because of scriptlet output, so read bytes
+        let mut buf = Vec::new();
+        let mut reader = reader;
+        loop {
+            buf.clear();
+            if reader.read_until(b'\n', &mut buf)? == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buf);
+            let Some(caps) = line_re.captures(line.trim_end()) else {
+                continue;
+            };
+            let Some(time) = parse_timestamp(&caps[1]) else {
+                continue;
+            };
+            let message = &caps[3];
+
+            match (&caps[2], message) {
+                ("PACMAN", "starting full system upgrade") => full_upgrade = true,
+                ("PACMAN", m) if m.starts_with("Running ") => full_upgrade = false,
+                ("ALPM", "transaction completed") => {
+                    if std::mem::take(&mut full_upgrade) {
+                        log.last_full_upgrade = Some(time);
+                    }
+                }
+                ("ALPM", "transaction failed" | "transaction interrupted") => full_upgrade = false,
+                _ => {
+                    if let Some(action) = action_re.captures(message) {
+                        let pkgname = &action[2];
+                        if !filter(pkgname) {
+                            continue;
+                        }
+                        match &action[1] {
+                            "removed" => {
+                                log.last_changed.remove(pkgname);
+                            }
+                            kind => {
+                                log.last_changed.insert(pkgname.to_owned(), time);
+                                if let ("downgraded", Some(to)) = (kind, action.get(4)) {
+                                    let change = Change {
+                                        time,
+                                        from: action[3].to_owned(),
+                                        to: to.as_str().to_owned(),
+                                    };
+                                    log.downgrades
+                                        .entry(pkgname.to_owned())
+                                        .or_default()
+                                        .push(change);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(log)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // excerpts in the format of pacman 6
+    const SYU: &str = "\
+[2024-02-10T09:12:41+0100] [PACMAN] Running 'pacman -Syu'
+[2024-02-10T09:12:41+0100] [PACMAN] synchronizing package lists
+[2024-02-10T09:12:43+0100] [PACMAN] starting full system upgrade
+[2024-02-10T09:13:01+0100] [ALPM] running '60-mkinitcpio-remove.hook'...
+[2024-02-10T09:13:02+0100] [ALPM] transaction started
+[2024-02-10T09:13:02+0100] [ALPM] upgraded glibc (2.39-1 -> 2.39-2)
+[2024-02-10T09:13:03+0100] [ALPM-SCRIPTLET] Generating locales...
+[2024-02-10T09:13:04+0100] [ALPM] upgraded linux (6.7.3.arch1-1 -> 6.7.4.arch1-1)
+[2024-02-10T09:13:05+0100] [ALPM] transaction completed
+[2024-02-10T09:13:05+0100] [ALPM] running '90-mkinitcpio-install.hook'...
+[2024-02-10T09:13:06+0100] [ALPM-SCRIPTLET] ==> Building image from preset: 'default'
+";
+    const DECLINED_SYU: &str = "\
+[2024-02-11T10:00:00+0100] [PACMAN] Running 'pacman -Syu'
+[2024-02-11T10:00:00+0100] [PACMAN] synchronizing package lists
+[2024-02-11T10:00:02+0100] [PACMAN] starting full system upgrade
+";
+    const INSTALL: &str = "\
+[2024-02-11T10:00:10+0100] [PACMAN] Running 'pacman -S htop'
+[2024-02-11T10:00:11+0100] [ALPM] transaction started
+[2024-02-11T10:00:12+0100] [ALPM] installed htop (3.3.0-1)
+[2024-02-11T10:00:12+0100] [ALPM] transaction completed
+";
+    const DOWNGRADE: &str = "\
+[2024-02-12T08:20:00+0100] [PACMAN] Running 'pacman -U linux-6.7.3.arch1-1-x86_64.pkg.tar.zst'
+[2024-02-12T08:20:02+0100] [ALPM] transaction started
+[2024-02-12T08:20:03+0100] [ALPM] downgraded linux (6.7.4.arch1-1 -> 6.7.3.arch1-1)
+[2024-02-12T08:20:05+0100] [ALPM] transaction completed
+";
+    // with linux in IgnorePkg, which pacman only mentions on the terminal
+    const IGNORED_SYU: &str = "\
+[2024-02-12T08:30:00+0100] [PACMAN] Running 'pacman -Syu'
+[2024-02-12T08:30:00+0100] [PACMAN] synchronizing package lists
+[2024-02-12T08:30:02+0100] [PACMAN] starting full system upgrade
+[2024-02-12T08:30:04+0100] [ALPM] transaction started
+[2024-02-12T08:30:04+0100] [ALPM] upgraded firefox (122.0-1 -> 122.0.1-1)
+[2024-02-12T08:30:05+0100] [ALPM] transaction completed
+";
+
+    fn parse(sessions: &[&str]) -> Log {
+        Log::parse(sessions.concat().as_bytes(), |_| true).unwrap()
+    }
+
+    #[test]
+    fn timestamps() {
+        let cases = [
+            ("2024-01-31T10:16:01+0100", Some(1706692561)),
+            ("2024-01-31T04:16:01-0500", Some(1706692561)),
+            ("2024-01-31T09:16:01+0000", Some(1706692561)),
+            // before pacman 5.1, in local time
+            ("2018-05-01 10:16", Some(1525169760)),
+            ("2024-01-31", None),
+            ("31/01/2024 10:16", None),
+        ];
+        for (timestamp, secs) in cases {
+            assert_eq!(parse_timestamp(timestamp), secs, "{timestamp}");
+        }
+    }
+
+    #[test]
+    fn full_upgrade() {
+        let log = parse(&[SYU]);
+        assert_eq!(log.last_full_upgrade, Some(1707552785));
+        assert_eq!(log.last_changed["linux"], 1707552784);
+        assert!(log.downgrades.is_empty());
+
+        let old = "\
+[2017-12-31 23:59] [PACMAN] Running 'pacman -Syu'
+[2017-12-31 23:59] [PACMAN] starting full system upgrade
+[2017-12-31 23:59] [ALPM] transaction started
+[2017-12-31 23:59] [ALPM] upgraded linux (4.14.8-1 -> 4.14.9-1)
+[2017-12-31 23:59] [ALPM] transaction completed
+";
+        assert_eq!(parse(&[old]).last_full_upgrade, Some(1514764740));
+    }
+
+    #[test]
+    fn declined_full_upgrade() {
+        assert_eq!(parse(&[SYU, DECLINED_SYU]).last_full_upgrade, Some(1707552785));
+        // the transaction of the next command isn't the full upgrade's
+        let log = parse(&[SYU, DECLINED_SYU, INSTALL]);
+        assert_eq!(log.last_full_upgrade, Some(1707552785));
+        assert_eq!(log.last_changed["htop"], 1707642012);
+    }
+
+    #[test]
+    fn install_is_not_full_upgrade() {
+        let log = parse(&[INSTALL]);
+        assert_eq!(log.last_full_upgrade, None);
+        assert_eq!(log.last_changed["htop"], 1707642012);
+    }
+
+    #[test]
+    fn downgrade() {
+        let log = parse(&[SYU, DOWNGRADE]);
+        assert_eq!(log.last_full_upgrade, Some(1707552785));
+        assert_eq!(log.last_changed["linux"], 1707722403);
+        let [change] = &log.downgrades["linux"][..] else { panic!("{:?}", log.downgrades) };
+        assert_eq!(
+            (change.time, &*change.from, &*change.to),
+            (1707722403, "6.7.4.arch1-1", "6.7.3.arch1-1")
+        );
+    }
+
+    #[test]
+    fn ignored_package() {
+        let log = parse(&[SYU, DOWNGRADE, IGNORED_SYU]);
+        assert_eq!(log.last_full_upgrade, Some(1707723005));
+        assert_eq!(log.last_changed["linux"], 1707722403);
+        assert_eq!(log.last_changed["firefox"], 1707723004);
+    }
+
+    #[test]
+    fn filter() {
+        let log = Log::parse([SYU, DOWNGRADE].concat().as_bytes(), |p| p == "glibc").unwrap();
+        assert_eq!(log.last_full_upgrade, Some(1707552785));
+        assert!(log.last_changed.contains_key("glibc"));
+        assert!(!log.last_changed.contains_key("linux") && log.downgrades.is_empty());
+    }
+}
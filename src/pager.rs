@@ -0,0 +1,99 @@
+//! Show long text output in a pager
+//!
+//! The output is collected in memory first, so that it's only paged when it doesn't fit on the
+//! terminal. The pager is `$CHECKUPGRADES_PAGER` or `$PAGER`, falling back to `less`, and is run
+//! with the shell like git does so it can include options.
+
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
+
+use clap::builder::PossibleValue;
+
+use crate::warning;
+
+/// When to use a pager
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Paging {
+    Auto,
+    Always,
+    Never,
+}
+
+impl clap::ValueEnum for Paging {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Auto, Self::Always, Self::Never]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Auto => PossibleValue::new("auto")
+                .help("Page when stdout is a terminal and the output doesn't fit on it"),
+            Self::Always => PossibleValue::new("always").help("Always page the output"),
+            Self::Never => PossibleValue::new("never").help("Never page the output"),
+        })
+    }
+}
+
+/// The pager command, `None` if paging is disabled by setting it to empty or `cat`
+fn pager_command() -> Option<String> {
+    let pager = env::var("CHECKUPGRADES_PAGER")
+        .or_else(|_| env::var("PAGER"))
+        .unwrap_or_else(|_| "less".to_owned());
+    let pager = pager.trim();
+    (!pager.is_empty() && pager != "cat").then(|| pager.to_owned())
+}
+
+/// Whether `text` is too long to fit on the terminal on stdout
+fn too_long(text: &[u8]) -> bool {
+    let stdout = io::stdout();
+    if !stdout.is_terminal() {
+        return false;
+    }
+    let lines = text.iter().filter(|&&b| b == b'\n').count();
+    match rustix::termios::tcgetwinsize(stdout) {
+        Ok(size) if size.ws_row > 0 => lines >= size.ws_row.into(),
+        _ => false,
+    }
+}
+
+/// Run `pager` and write `text` to it, waiting for it to exit
+fn run_pager(pager: &str, text: &[u8]) -> io::Result<()> {
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(pager).stdin(Stdio::piped());
+    // like git: quit if it fits on one screen, pass colors through, and don't clear the screen
+    if env::var_os("LESS").is_none() {
+        cmd.env("LESS", "FRX");
+    }
+    if env::var_os("LV").is_none() {
+        cmd.env("LV", "-c");
+    }
+    let mut child = cmd.spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    match stdin.write_all(text) {
+        // the user quit the pager before reading everything
+        Err(err) if err.kind() == io::ErrorKind::BrokenPipe => {}
+        result => result?,
+    }
+    drop(stdin);
+    child.wait()?;
+    Ok(())
+}
+
+/// Write `text` to stdout, through a pager if `paging` says so
+pub fn write(text: &[u8], paging: Paging) -> io::Result<()> {
+    let use_pager = match paging {
+        Paging::Always => true,
+        Paging::Auto => too_long(text),
+        Paging::Never => false,
+    };
+    if let Some(pager) = pager_command().filter(|_| use_pager) {
+        match run_pager(&pager, text) {
+            Ok(()) => return Ok(()),
+            Err(err) => warning!("failed to run pager {pager:?}: {err}"),
+        }
+    }
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(text)?;
+    stdout.flush()
+}
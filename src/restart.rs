@@ -0,0 +1,337 @@
+//! Predict which running processes will need to be restarted after an upgrade
+//!
+//! When a package is upgraded, processes which have its old shared libraries (or executables)
+//! mapped into memory keep running the old code until they're restarted. We find them by
+//! comparing the file lists of the locally installed versions of upgradable packages against the
+//! file-backed mappings in `/proc/$pid/maps`.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use ahash::HashMap;
+use anyhow::Context;
+use bstr::ByteSlice;
+
+/// A running process which maps at least one file that will be replaced by an upgrade
+#[derive(Debug)]
+pub struct Process {
+    pub pid: u32,
+    /// Target of `/proc/$pid/exe`, if we're allowed to read it
+    pub exe: Option<PathBuf>,
+    /// The systemd unit (service or scope) that the process belongs to, if any
+    pub unit: Option<String>,
+    /// Names of the upgraded packages which own the mapped files, sorted and deduplicated
+    pub packages: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct RestartReport {
+    /// Affected processes, sorted by pid
+    pub processes: Vec<Process>,
+    /// Number of processes whose maps couldn't be read, usually because they belong to another
+    /// user and we're not root.
+    pub inaccessible: usize,
+}
+
+/// Scan all processes under `proc_root` (normally `/proc`) for mappings of any of the given
+/// files.
+///
+/// `files` maps a pkgname to the list of files it owns, as returned by
+/// [`crate::alpm::local_package_files`]. Processes which exit while we're scanning are silently
+/// skipped.
+pub fn find_processes(
+    proc_root: impl AsRef<Path>,
+    files: &HashMap<String, Vec<String>>,
+) -> anyhow::Result<RestartReport> {
+    let proc_root = proc_root.as_ref();
+
+    // map of absolute path -> owning pkgname
+    let owners: HashMap<String, &str> = files
+        .iter()
+        .flat_map(|(pkgname, files)| files.iter().map(move |f| (format!("/{f}"), &**pkgname)))
+        .collect();
+
+    let self_pid = rustix::process::getpid().as_raw_nonzero().get() as u32;
+    let dirents = proc_root
+        .read_dir()
+        .with_context(|| format!("failed to read directory {}", proc_root.display()))?;
+
+    let mut report = RestartReport::default();
+    for result in dirents {
+        let entry =
+            result.with_context(|| format!("failed to read dirent in {}", proc_root.display()))?;
+
+        // only numeric directory names are processes
+        let pid: u32 = match entry.file_name().to_str().and_then(|s| s.parse().ok()) {
+            Some(pid) => pid,
+            None => continue,
+        };
+        if pid == self_pid {
+            continue;
+        }
+
+        let dir = entry.path();
+        let maps = match fs::read(dir.join("maps")) {
+            Ok(maps) => maps,
+            Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                report.inaccessible += 1;
+                continue;
+            }
+            // most likely the process exited
+            Err(_) => continue,
+        };
+
+        let mut packages: Vec<String> = maps
+            .lines()
+            .filter_map(|line| mapped_path(line.to_str().ok()?))
+            .filter_map(|path| owners.get(path))
+            .map(|pkgname| (*pkgname).to_owned())
+            .collect();
+        if packages.is_empty() {
+            continue;
+        }
+        packages.sort_unstable();
+        packages.dedup();
+
+        report.processes.push(Process {
+            pid,
+            exe: fs::read_link(dir.join("exe")).ok().map(|exe| {
+                // an exe replaced on disk links to its old path with a suffix
+                match exe.to_str().and_then(|s| s.strip_suffix(" (deleted)")) {
+                    Some(path) => PathBuf::from(path),
+                    None => exe,
+                }
+            }),
+            unit: fs::read_to_string(dir.join("cgroup")).ok().and_then(|s| systemd_unit(&s)),
+            packages,
+        });
+    }
+
+    report.processes.sort_unstable_by_key(|p| p.pid);
+    Ok(report)
+}
+
+/// Get the path of a file-backed mapping from a line of `/proc/$pid/maps`.
+///
+/// The address, permission, offset, device, and inode fields never contain a `/`, so the path
+/// starts at the first one. Anonymous and pseudo mappings like `[heap]` return None.
+fn mapped_path(line: &str) -> Option<&str> {
+    let path = &line[line.find('/')?..];
+    Some(path.strip_suffix(" (deleted)").unwrap_or(path))
+}
+
+/// Find the systemd unit name from the contents of `/proc/$pid/cgroup`.
+///
+/// Uses the unified (cgroup v2) hierarchy if present, falling back to the v1 `name=systemd`
+/// hierarchy. The innermost `.service` or `.scope` component of the path is the unit.
+fn systemd_unit(cgroup: &str) -> Option<String> {
+    let path = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .or_else(|| cgroup.lines().find_map(|line| Some(line.split_once(":name=systemd:")?.1)))?;
+    path.rsplit('/').find(|c| c.ends_with(".service") || c.ends_with(".scope")).map(str::to_owned)
+}
+
+/// Write a table of the processes in `report`.
+pub fn write_report(out: &mut impl Write, report: &RestartReport) -> io::Result<()> {
+    if report.processes.is_empty() {
+        writeln!(out, "No running processes need to be restarted.")?;
+    } else {
+        let exes: Vec<String> = report
+            .processes
+            .iter()
+            .map(|p| match &p.exe {
+                Some(exe) => exe.display().to_string(),
+                None => "?".into(),
+            })
+            .collect();
+        let pid_width = report.processes.iter().map(|p| p.pid.to_string().len()).max().unwrap();
+        let exe_width = exes.iter().map(String::len).max().unwrap();
+        let unit_width =
+            report.processes.iter().map(|p| p.unit.as_deref().unwrap_or("-").len()).max().unwrap();
+
+        writeln!(out, "Processes to restart: {:5}", report.processes.len())?;
+        for (p, exe) in report.processes.iter().zip(exes.iter()) {
+            writeln!(
+                out,
+                "  {pid:>pid_width$}  {exe:exe_width$}  {unit:unit_width$}  {packages}",
+                pid = p.pid,
+                unit = p.unit.as_deref().unwrap_or("-"),
+                packages = p.packages.join(", "),
+            )?;
+        }
+    }
+
+    if report.inaccessible > 0 {
+        writeln!(
+            out,
+            "Note: {} processes could not be inspected, run as root for a complete report",
+            report.inaccessible
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    use super::*;
+
+    /// Add a process to a fake /proc, each entry is only created if given
+    fn add_process(
+        root: &Path,
+        pid: u32,
+        maps: Option<&str>,
+        exe: Option<&str>,
+        cgroup: Option<&str>,
+    ) {
+        let dir = root.join(pid.to_string());
+        fs::create_dir(&dir).unwrap();
+        if let Some(maps) = maps {
+            fs::write(dir.join("maps"), maps).unwrap();
+        }
+        if let Some(exe) = exe {
+            symlink(exe, dir.join("exe")).unwrap();
+        }
+        if let Some(cgroup) = cgroup {
+            fs::write(dir.join("cgroup"), cgroup).unwrap();
+        }
+    }
+
+    fn files() -> HashMap<String, Vec<String>> {
+        [
+            ("glibc", vec!["usr/lib/libc.so.6", "usr/lib/ld-linux-x86-64.so.2"]),
+            ("openssl", vec!["usr/lib/libssl.so.3"]),
+            ("foo", vec!["usr/bin/foo"]),
+        ]
+        .into_iter()
+        .map(|(pkgname, files)| (pkgname.to_owned(), files.into_iter().map(String::from).collect()))
+        .collect()
+    }
+
+    #[test]
+    fn mapped_paths() {
+        let line = "7f0e1c000000-7f0e1c022000 r--p 00000000 00:1a 1234  /usr/lib/libc.so.6";
+        assert_eq!(mapped_path(line), Some("/usr/lib/libc.so.6"));
+        let line =
+            "7f0e1c000000-7f0e1c022000 r-xp 00001000 00:1a 1234  /usr/lib/libssl.so.3 (deleted)";
+        assert_eq!(mapped_path(line), Some("/usr/lib/libssl.so.3"));
+        assert_eq!(mapped_path("7ffd4a000000-7ffd4a021000 rw-p 00000000 00:00 0  [stack]"), None);
+        assert_eq!(mapped_path("7ffd4a000000-7ffd4a021000 rw-p 00000000 00:00 0"), None);
+    }
+
+    #[test]
+    fn systemd_units() {
+        let cases = [
+            ("0::/system.slice/sshd.service\n", Some("sshd.service")),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/\
+                 app-firefox@1234.service\n",
+                Some("app-firefox@1234.service"),
+            ),
+            (
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/\
+                 app-gnome-foot-5678.scope\n",
+                Some("app-gnome-foot-5678.scope"),
+            ),
+            ("0::/user.slice/user-1000.slice/session-2.scope\n", Some("session-2.scope")),
+            (
+                "12:pids:/system.slice/cron.service\n1:name=systemd:/system.slice/cron.service\n",
+                Some("cron.service"),
+            ),
+            ("0::/\n", None),
+            ("", None),
+        ];
+        for (cgroup, unit) in cases {
+            assert_eq!(systemd_unit(cgroup).as_deref(), unit, "{cgroup:?}");
+        }
+    }
+
+    #[test]
+    fn fake_proc() {
+        let root = tempfile::tempdir().unwrap();
+        let root = root.path();
+        // maps a deleted library
+        add_process(
+            root,
+            100,
+            Some(
+                "55d0c0000000-55d0c0001000 r--p 00000000 00:1a 11  /usr/bin/sshd\n\
+                 7f0e1c000000-7f0e1c022000 r--p 00000000 00:1a 12  /usr/lib/libc.so.6 (deleted)\n\
+                 7f0e1d000000-7f0e1d022000 r--p 00000000 00:1a 13  /usr/lib/libssl.so.3\n\
+                 7ffd4a000000-7ffd4a021000 rw-p 00000000 00:00 0  [stack]\n",
+            ),
+            Some("/usr/bin/sshd"),
+            Some("0::/system.slice/sshd.service\n"),
+        );
+        // its exe was replaced by the new version
+        add_process(
+            root,
+            200,
+            Some("55d0c0000000-55d0c0001000 r-xp 00000000 00:1a 21  /usr/bin/foo (deleted)\n"),
+            Some("/usr/bin/foo (deleted)"),
+            Some(
+                "0::/user.slice/user-1000.slice/user@1000.service/app.slice/\
+                 app-foo@1.service\n",
+            ),
+        );
+        // maps nothing being upgraded
+        add_process(
+            root,
+            300,
+            Some("55d0c0000000-55d0c0001000 r-xp 00000000 00:1a 31  /usr/bin/bar\n"),
+            Some("/usr/bin/bar"),
+            None,
+        );
+        // exited after being listed, so nothing can be read
+        add_process(root, 400, None, None, None);
+        // belongs to another user, the exe and cgroup can't be read either
+        add_process(
+            root,
+            500,
+            Some("7f0e1c000000-7f0e1c022000 r--p 0 0 0  /usr/lib/libc.so.6\n"),
+            None,
+            None,
+        );
+        fs::set_permissions(root.join("500/maps"), fs::Permissions::from_mode(0o000)).unwrap();
+        // not processes
+        fs::create_dir(root.join("self")).unwrap();
+        fs::write(root.join("uptime"), "1.00 2.00\n").unwrap();
+
+        let report = find_processes(root, &files()).unwrap();
+        // root can read anything, so the "other user" is only inaccessible to others
+        let is_root = rustix::process::geteuid().is_root();
+        let pids: Vec<u32> = report.processes.iter().map(|p| p.pid).collect();
+        if is_root {
+            assert_eq!(pids, [100, 200, 500]);
+            assert_eq!(report.inaccessible, 0);
+        } else {
+            assert_eq!(pids, [100, 200]);
+            assert_eq!(report.inaccessible, 1);
+        }
+
+        let sshd = &report.processes[0];
+        assert_eq!(sshd.exe.as_deref(), Some(Path::new("/usr/bin/sshd")));
+        assert_eq!(sshd.unit.as_deref(), Some("sshd.service"));
+        assert_eq!(sshd.packages, ["glibc", "openssl"]);
+
+        let foo = &report.processes[1];
+        assert_eq!(foo.exe.as_deref(), Some(Path::new("/usr/bin/foo")));
+        assert_eq!(foo.unit.as_deref(), Some("app-foo@1.service"));
+        assert_eq!(foo.packages, ["foo"]);
+
+        if is_root {
+            let other = &report.processes[2];
+            assert_eq!((other.exe.as_deref(), other.unit.as_deref()), (None, None));
+            assert_eq!(other.packages, ["glibc"]);
+        }
+    }
+
+    #[test]
+    fn missing_proc_root() {
+        let root = tempfile::tempdir().unwrap();
+        assert!(find_processes(root.path().join("missing"), &files()).is_err());
+    }
+}
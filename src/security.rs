@@ -0,0 +1,218 @@
+//! Match pending upgrades against the Arch Linux security tracker
+//!
+//! The tracker's list of issue groups (AVG-*) is fetched from `ISSUES_URL` and cached for an hour
+//! in `$XDG_CACHE_HOME/checkupgrades/security.json`, or read from a file in the same format. An
+//! upgrade fixes a group when the installed version is older than the group's fixed version and
+//! the new version isn't.
+
+use std::cmp::Reverse;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use ahash::HashMap;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::{download, version, Upgrade};
+
+/// The tracker's JSON list of all issue groups
+pub const ISSUES_URL: &str = "https://security.archlinux.org/issues/all.json";
+
+/// How long a fetched list is used before fetching it again, in seconds
+const MAX_CACHE_AGE: u64 = 3600;
+
+/// How bad the issues of a group are, as rated by the tracker
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(from = "String")]
+pub enum Severity {
+    Unknown,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl Severity {
+    /// The tracker's name of the severity
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "Unknown",
+            Self::Low => "Low",
+            Self::Medium => "Medium",
+            Self::High => "High",
+            Self::Critical => "Critical",
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl Serialize for Severity {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl From<String> for Severity {
+    fn from(s: String) -> Self {
+        match &*s {
+            "Low" => Self::Low,
+            "Medium" => Self::Medium,
+            "High" => Self::High,
+            "Critical" => Self::Critical,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+/// A group of issues affecting the same packages, as listed by the tracker
+#[derive(Debug, Deserialize)]
+struct Group {
+    name: String,
+    packages: Vec<String>,
+    status: String,
+    severity: Severity,
+    #[serde(rename = "type")]
+    kind: String,
+    fixed: Option<String>,
+    issues: Vec<String>,
+    #[serde(default)]
+    advisories: Vec<String>,
+}
+
+/// A group of issues fixed by an upgrade
+#[derive(Debug, Clone, Serialize)]
+pub struct Fix {
+    /// The tracker's group ID, `AVG-1234`
+    pub group: String,
+    pub severity: Severity,
+    /// The kind of vulnerability, e.g. `arbitrary code execution`
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// The CVE IDs of the issues
+    pub cves: Vec<String>,
+    /// The advisories published for the group, `ASA-202401-01`
+    pub advisories: Vec<String>,
+}
+
+/// The list of issue groups
+#[derive(Debug)]
+pub struct Tracker {
+    groups: Vec<Group>,
+}
+
+impl Tracker {
+    /// Load the issue list from `file`, or fetch it from the tracker
+    pub fn load(file: Option<&Path>) -> Result<Self> {
+        let path = match file {
+            Some(file) => file.to_owned(),
+            None => download::fetch_cached(ISSUES_URL, "security.json", MAX_CACHE_AGE)?,
+        };
+        let contents =
+            fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let groups = serde_json::from_slice(&contents)
+            .with_context(|| format!("invalid security issue list in {}", path.display()))?;
+        Ok(Self { groups })
+    }
+
+    /// Set `security` on each upgrade to the groups it fixes, most severe first
+    pub fn annotate(&self, upgrades: &mut [Upgrade]) {
+        let mut by_pkgname: HashMap<&str, Vec<&Group>> = HashMap::default();
+        for group in self.groups.iter().filter(|g| g.status != "Not affected") {
+            for pkgname in group.packages.iter() {
+                by_pkgname.entry(pkgname).or_default().push(group);
+            }
+        }
+        for u in upgrades.iter_mut() {
+            let Some(groups) = by_pkgname.get(&*u.pkgname) else {
+                continue;
+            };
+            u.security = groups
+                .iter()
+                .filter(|g| {
+                    g.fixed.as_deref().is_some_and(|fixed| {
+                        version::vercmp(&u.oldver, fixed).is_lt()
+                            && version::vercmp(&u.newver, fixed).is_ge()
+                    })
+                })
+                .map(|g| Fix {
+                    group: g.name.clone(),
+                    severity: g.severity,
+                    kind: g.kind.clone(),
+                    cves: g.issues.clone(),
+                    advisories: g.advisories.clone(),
+                })
+                .collect();
+            u.security.sort_by_key(|fix| Reverse(fix.severity));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> Tracker {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/security-all.json");
+        Tracker::load(Some(&path)).unwrap()
+    }
+
+    #[test]
+    fn annotate() {
+        let cases: &[(&str, &[&str])] = &[
+            // the new version is the fixed one
+            ("openssl 3.0.12-1 -> 3.0.13-1", &["AVG-2001"]),
+            ("lib32-openssl 3.0.12-1 -> 3.0.13-2", &["AVG-2001"]),
+            // several groups, most severe first
+            ("openssl 3.0.11-1 -> 3.0.13-1", &["AVG-2001", "AVG-2002"]),
+            ("openssl 3.0.11-1 -> 3.0.14-1", &["AVG-2001", "AVG-2003", "AVG-2002"]),
+            // the new version isn't fixed yet
+            ("curl 8.5.0-1 -> 8.5.0-2", &[]),
+            // the installed version is already fixed
+            ("openssl 3.0.13-1 -> 3.0.13-2", &[]),
+            ("zlib 1:1.3-1 -> 1:1.3.1-1", &[]),
+            ("bash 5.2.026-1 -> 5.2.026-2", &[]),
+            // epochs
+            ("ffmpeg 2:6.1-3 -> 2:6.1.1-1", &["AVG-2007"]),
+            ("ffmpeg 6.2-1 -> 2:6.1.1-1", &["AVG-2007"]),
+            ("gnupg 2.5-1 -> 1:2.0-1", &["AVG-2008"]),
+            ("gnupg 2.5-1 -> 2.6-1", &[]),
+            ("gnupg 1:2.0-1 -> 1:2.1-1", &[]),
+            ("vim 9.1-1 -> 9.2-1", &[]),
+        ];
+        let tracker = tracker();
+        for (line, groups) in cases {
+            let mut upgrades = [line.parse::<Upgrade>().unwrap()];
+            tracker.annotate(&mut upgrades);
+            let found: Vec<&str> = upgrades[0].security.iter().map(|f| &*f.group).collect();
+            assert_eq!(found, *groups, "{line}");
+        }
+    }
+
+    #[test]
+    fn fix_details() {
+        let mut upgrades = ["openssl 3.0.12-1 -> 3.0.13-1".parse::<Upgrade>().unwrap()];
+        tracker().annotate(&mut upgrades);
+        let fix = &upgrades[0].security[0];
+        assert_eq!(fix.severity, Severity::Critical);
+        assert_eq!(fix.kind, "arbitrary code execution");
+        assert_eq!(fix.cves, ["CVE-2024-0001", "CVE-2024-0002"]);
+        assert_eq!(fix.advisories, ["ASA-202401-01"]);
+    }
+
+    #[test]
+    fn severities() {
+        assert_eq!(Severity::from("Critical".to_owned()), Severity::Critical);
+        assert_eq!(Severity::from("bogus".to_owned()), Severity::Unknown);
+        for severity in [Severity::Unknown, Severity::Low, Severity::High, Severity::Critical] {
+            assert_eq!(Severity::from(severity.to_string()), severity);
+            assert_eq!(serde_json::to_value(severity).unwrap(), severity.as_str());
+        }
+        assert!(Severity::Unknown < Severity::Low && Severity::High < Severity::Critical);
+    }
+}
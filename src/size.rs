@@ -0,0 +1,196 @@
+//! Formatting of byte sizes
+//!
+//! All arithmetic is done on integers, so that large totals are exact and rounding is consistent.
+
+use clap::builder::PossibleValue;
+
+/// Which unit to display sizes in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// The largest unit in which the rounded value is at least 1
+    Auto,
+    Bytes,
+    Kilo,
+    Mega,
+    Giga,
+}
+
+impl clap::ValueEnum for Unit {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Auto, Self::Bytes, Self::Kilo, Self::Mega, Self::Giga]
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        Some(match self {
+            Self::Auto => PossibleValue::new("auto").help("Pick a unit for each size"),
+            Self::Bytes => PossibleValue::new("b").help("Bytes"),
+            Self::Kilo => PossibleValue::new("k").help("KiB, or kB with --si"),
+            Self::Mega => PossibleValue::new("m").help("MiB, or MB with --si"),
+            Self::Giga => PossibleValue::new("g").help("GiB, or GB with --si"),
+        })
+    }
+}
+
+/// How to format sizes: a unit, and whether to use powers of 1000 (SI) or 1024 (IEC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeFormat {
+    pub unit: Unit,
+    pub si: bool,
+}
+
+impl Default for SizeFormat {
+    /// Fixed MiB, which is what checkupgrades has always shown
+    fn default() -> Self {
+        Self { unit: Unit::Mega, si: false }
+    }
+}
+
+impl SizeFormat {
+    /// The longest string `format` returns for sizes below 10000 GiB, for aligning columns
+    pub const WIDTH: usize = "-9999.99 MiB".len();
+
+    fn base(self) -> u64 {
+        if self.si {
+            1000
+        } else {
+            1024
+        }
+    }
+
+    /// Pick the unit to display `bytes` in
+    fn unit_for(self, bytes: u64) -> Unit {
+        match self.unit {
+            Unit::Auto => {
+                let base = self.base();
+                if bytes >= base * base * base {
+                    Unit::Giga
+                } else if bytes >= base * base {
+                    Unit::Mega
+                } else if bytes >= base {
+                    Unit::Kilo
+                } else {
+                    Unit::Bytes
+                }
+            }
+            unit => unit,
+        }
+    }
+
+    fn suffix(self, unit: Unit) -> &'static str {
+        match (unit, self.si) {
+            (Unit::Auto | Unit::Bytes, _) => "B",
+            (Unit::Kilo, false) => "KiB",
+            (Unit::Mega, false) => "MiB",
+            (Unit::Giga, false) => "GiB",
+            (Unit::Kilo, true) => "kB",
+            (Unit::Mega, true) => "MB",
+            (Unit::Giga, true) => "GB",
+        }
+    }
+
+    /// Format the magnitude of a size, with `sign` prepended to the number
+    fn format_with_sign(self, bytes: u64, sign: &str) -> String {
+        let mut unit = self.unit_for(bytes);
+        // hundredths of the unit, rounded half up
+        let hundredths = |unit: Unit| {
+            let divisor = u128::from(self.base()).pow(match unit {
+                Unit::Auto | Unit::Bytes => 0,
+                Unit::Kilo => 1,
+                Unit::Mega => 2,
+                Unit::Giga => 3,
+            });
+            (u128::from(bytes) * 100 + divisor / 2) / divisor
+        };
+        // a value which rounds up to 1024.00 KiB is shown as 1.00 MiB
+        if self.unit == Unit::Auto && hundredths(unit) >= u128::from(self.base()) * 100 {
+            unit = match unit {
+                Unit::Kilo => Unit::Mega,
+                Unit::Mega => Unit::Giga,
+                unit => unit,
+            };
+        }
+        if let Unit::Auto | Unit::Bytes = unit {
+            return format!("{sign}{bytes} B");
+        }
+        let hundredths = hundredths(unit);
+        format!("{sign}{}.{:02} {}", hundredths / 100, hundredths % 100, self.suffix(unit))
+    }
+
+    /// Format a size, e.g. `12.34 MiB`
+    pub fn format(self, bytes: u64) -> String {
+        self.format_with_sign(bytes, "")
+    }
+
+    /// Format a size change, with a `+` or `-` sign unless it's zero
+    pub fn format_signed(self, bytes: i64) -> String {
+        let sign = match bytes.signum() {
+            1 => "+",
+            -1 => "-",
+            _ => "",
+        };
+        self.format_with_sign(bytes.unsigned_abs(), sign)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        let iec = SizeFormat { unit: Unit::Auto, si: false };
+        let si = SizeFormat { unit: Unit::Auto, si: true };
+        let cases = [
+            (iec, 0, "0 B"),
+            (iec, 1023, "1023 B"),
+            (iec, 1024, "1.00 KiB"),
+            (iec, 1535, "1.50 KiB"),
+            // 1.004 and 1.006 KiB
+            (iec, 1028, "1.00 KiB"),
+            (iec, 1030, "1.01 KiB"),
+            (iec, 1048575, "1.00 MiB"),
+            (iec, 1048576, "1.00 MiB"),
+            (iec, 1073741823, "1.00 GiB"),
+            (iec, 10 << 40, "10240.00 GiB"),
+            (iec, u64::MAX, "17179869184.00 GiB"),
+            (si, 999, "999 B"),
+            (si, 1000, "1.00 kB"),
+            (si, 1024, "1.02 kB"),
+            (si, 999_994, "999.99 kB"),
+            (si, 999_995, "1.00 MB"),
+            (si, 1_000_000_000, "1.00 GB"),
+            // the default, fixed MiB, where 0.005 MiB is 5242.88 bytes
+            (SizeFormat::default(), 0, "0.00 MiB"),
+            (SizeFormat::default(), 5242, "0.00 MiB"),
+            (SizeFormat::default(), 5243, "0.01 MiB"),
+            (SizeFormat::default(), 1 << 30, "1024.00 MiB"),
+            // a fixed unit isn't changed when the value rounds up
+            (SizeFormat { unit: Unit::Kilo, si: false }, 1048575, "1024.00 KiB"),
+            (SizeFormat { unit: Unit::Giga, si: true }, 1, "0.00 GB"),
+            (SizeFormat { unit: Unit::Bytes, si: false }, 123456789, "123456789 B"),
+            (SizeFormat { unit: Unit::Bytes, si: true }, 0, "0 B"),
+        ];
+        for (format, bytes, expected) in cases {
+            assert_eq!(format.format(bytes), expected, "{format:?} {bytes}");
+        }
+    }
+
+    #[test]
+    fn signed_sizes() {
+        let iec = SizeFormat { unit: Unit::Auto, si: false };
+        let bytes = SizeFormat { unit: Unit::Bytes, si: false };
+        let cases = [
+            (iec, 0, "0 B"),
+            (iec, 1536, "+1.50 KiB"),
+            (iec, -1536, "-1.50 KiB"),
+            (iec, -1048575, "-1.00 MiB"),
+            (SizeFormat::default(), -5243, "-0.01 MiB"),
+            (SizeFormat::default(), -5242, "-0.00 MiB"),
+            (bytes, -1, "-1 B"),
+            (bytes, i64::MIN, "-9223372036854775808 B"),
+        ];
+        for (format, size, expected) in cases {
+            assert_eq!(format.format_signed(size), expected, "{format:?} {size}");
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Sort orders for the list of upgrades
+
+use std::cmp::Ordering;
+
+use anyhow::anyhow;
+
+use crate::Upgrade;
+
+/// A property of an upgrade which can be sorted on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Repo,
+    Name,
+    Download,
+    Installed,
+    Net,
+    BuildDate,
+    Class,
+}
+
+impl SortField {
+    const NAMES: &'static str = "repo, name, download, installed, net, builddate, class";
+
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "repo" => Self::Repo,
+            "name" => Self::Name,
+            "download" => Self::Download,
+            "installed" => Self::Installed,
+            "net" => Self::Net,
+            "builddate" => Self::BuildDate,
+            "class" => Self::Class,
+            _ => return None,
+        })
+    }
+
+    fn compare(self, a: &Upgrade, b: &Upgrade) -> Ordering {
+        match self {
+            Self::Repo => a.repo.cmp(&b.repo),
+            Self::Name => a.pkgname.cmp(&b.pkgname),
+            Self::Download => a.download_size.cmp(&b.download_size),
+            Self::Installed => a.install_size.cmp(&b.install_size),
+            Self::Net => a.net_size().cmp(&b.net_size()),
+            Self::BuildDate => a.build_date.cmp(&b.build_date),
+            Self::Class => a.class.cmp(&b.class),
+        }
+    }
+}
+
+/// One key of a sort order, possibly reversed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl SortKey {
+    /// Parse a key like `download`, `download:desc`, or `name:asc`
+    pub fn parse(s: &str) -> anyhow::Result<Self> {
+        let (name, direction) = s.split_once(':').unwrap_or((s, "asc"));
+        let field = SortField::from_name(name).ok_or_else(|| {
+            anyhow!("unknown sort key {name:?}, expected one of {}", SortField::NAMES)
+        })?;
+        let descending = match direction {
+            "asc" => false,
+            "desc" => true,
+            _ => return Err(anyhow!("invalid sort direction {direction:?}, expected asc or desc")),
+        };
+        Ok(Self { field, descending })
+    }
+
+    /// The default sort order, by repo and then pkgname
+    pub fn default_order() -> Vec<Self> {
+        vec![
+            Self { field: SortField::Repo, descending: false },
+            Self { field: SortField::Name, descending: false },
+        ]
+    }
+}
+
+/// Sort upgrades by each key in turn. Upgrades which compare equal on all keys are sorted by
+/// pkgname so the output is always stable.
+pub fn sort_upgrades(upgrades: &mut [Upgrade], keys: &[SortKey]) {
+    upgrades.sort_unstable_by(|a, b| {
+        keys.iter()
+            .map(|key| {
+                let ord = key.field.compare(a, b);
+                if key.descending {
+                    ord.reverse()
+                } else {
+                    ord
+                }
+            })
+            .find(|ord| ord.is_ne())
+            .unwrap_or_else(|| a.pkgname.cmp(&b.pkgname))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Repo;
+
+    fn upgrade(repo: &str, pkgname: &str, download_size: u64) -> Upgrade {
+        let mut u: Upgrade = format!("{pkgname} 1.0-1 -> 1.1-1").parse().unwrap();
+        u.repo = Some(Repo::from(repo));
+        u.download_size = download_size;
+        u
+    }
+
+    fn sorted(keys: &[&str]) -> Vec<String> {
+        let mut upgrades = vec![
+            upgrade("extra", "c", 10),
+            upgrade("core", "d", 20),
+            upgrade("extra", "a", 10),
+            upgrade("core", "b", 30),
+            upgrade("extra", "e", 20),
+        ];
+        let keys: Vec<SortKey> = keys.iter().map(|k| SortKey::parse(k).unwrap()).collect();
+        sort_upgrades(&mut upgrades, &keys);
+        upgrades.into_iter().map(|u| u.pkgname).collect()
+    }
+
+    #[test]
+    fn sort_orders() {
+        assert_eq!(sorted(&["repo", "name"]), ["b", "d", "a", "c", "e"]);
+        // ties on the first key are broken by the next
+        assert_eq!(sorted(&["download:desc", "repo:desc"]), ["b", "e", "d", "a", "c"]);
+        assert_eq!(sorted(&["download", "name:desc"]), ["c", "a", "e", "d", "b"]);
+        // and by pkgname when all keys tie
+        assert_eq!(sorted(&["download"]), ["a", "c", "d", "e", "b"]);
+        assert_eq!(sorted(&[]), ["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn parse_keys() {
+        let key = |field, descending| SortKey { field, descending };
+        assert_eq!(SortKey::parse("name").unwrap(), key(SortField::Name, false));
+        assert_eq!(SortKey::parse("net:asc").unwrap(), key(SortField::Net, false));
+        assert_eq!(SortKey::parse("builddate:desc").unwrap(), key(SortField::BuildDate, true));
+        for bad in ["bogus", "name:up", "", ":desc"] {
+            assert!(SortKey::parse(bad).is_err(), "{bad:?}");
+        }
+    }
+}
@@ -0,0 +1,131 @@
+//! Output for status bars: waybar's custom module JSON, i3blocks' lines, and a bare count
+//!
+//! Each one carries classes for the state of the pending upgrades, so the bar can style them:
+//! `none` when there's nothing to do, `pending` otherwise, plus `many` past the threshold,
+//! `security` when an upgrade fixes security issues, and `reboot` when a package which needs a
+//! reboot to take effect will be upgraded.
+
+use std::io::{self, Write};
+
+use anstream::{AutoStream, ColorChoice};
+use serde::Serialize;
+
+use crate::{glob_match, output, Upgrade};
+
+/// Packages whose upgrade only takes effect after a reboot: kernels, firmware and microcode,
+/// init, the C library, and graphics drivers loaded into the kernel
+const REBOOT_PACKAGES: &[&str] = &[
+    "linux",
+    "linux-lts",
+    "linux-zen",
+    "linux-hardened",
+    "linux-rt*",
+    "linux-firmware*",
+    "amd-ucode",
+    "intel-ucode",
+    "systemd",
+    "glibc",
+    "nvidia*",
+];
+
+/// Colors for i3blocks, which are given as hex RGB instead of our SGR theme
+const SECURITY_COLOR: &str = "#FF5555";
+const REBOOT_COLOR: &str = "#FF79C6";
+const MANY_COLOR: &str = "#FFB86C";
+
+/// Whether upgrading `upgrade` needs a reboot to take effect
+pub fn needs_reboot(upgrade: &Upgrade) -> bool {
+    REBOOT_PACKAGES.iter().any(|glob| glob_match(glob, &upgrade.pkgname))
+}
+
+/// The state of the pending upgrades, as a list of classes for the bar's styling
+pub fn classes(upgrades: &[Upgrade], many: usize) -> Vec<&'static str> {
+    if upgrades.is_empty() {
+        return vec!["none"];
+    }
+    let mut classes = vec!["pending"];
+    if upgrades.len() >= many {
+        classes.push("many");
+    }
+    if upgrades.iter().any(|u| !u.security.is_empty()) {
+        classes.push("security");
+    }
+    if upgrades.iter().any(needs_reboot) {
+        classes.push("reboot");
+    }
+    classes
+}
+
+/// The output of a waybar custom module with `"return-type": "json"`
+#[derive(Serialize)]
+struct Waybar<'a> {
+    text: String,
+    /// Pango markup, so the list has to be escaped
+    tooltip: String,
+    class: &'a [&'a str],
+    /// Fills waybar's `{percentage}` and picks a `format-icons` entry, capped at 100 upgrades
+    percentage: usize,
+}
+
+/// Escape `text` for Pango markup
+fn escape_markup(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// The list of upgrades for a tooltip, without colors and not fit to the terminal
+pub fn tooltip(
+    upgrades: &[Upgrade],
+    cooling: &[Upgrade],
+    now: u64,
+    opts: &output::TextOptions,
+) -> io::Result<String> {
+    let mut out = AutoStream::new(Vec::new(), ColorChoice::Never);
+    let opts = output::TextOptions { width: None, ..opts.clone() };
+    output::write_list(&mut out, upgrades, cooling, now, &opts)?;
+    Ok(String::from_utf8_lossy(&out.into_inner()).into_owned())
+}
+
+/// Write the JSON line for waybar. `tooltip` is the formatted list of upgrades, without colors.
+pub fn write_waybar(
+    out: &mut impl Write,
+    upgrades: &[Upgrade],
+    tooltip: &str,
+    classes: &[&str],
+) -> io::Result<()> {
+    let doc = Waybar {
+        text: upgrades.len().to_string(),
+        tooltip: escape_markup(tooltip.trim_end()),
+        class: classes,
+        percentage: upgrades.len().min(100),
+    };
+    serde_json::to_writer(&mut *out, &doc)?;
+    writeln!(out)
+}
+
+/// Write the full text, short text, and color lines for i3blocks, which also suit polybar's and
+/// other bars' script modules since they only read the first line
+pub fn write_i3blocks(
+    out: &mut impl Write,
+    upgrades: &[Upgrade],
+    classes: &[&str],
+) -> io::Result<()> {
+    let count = upgrades.len();
+    let notes: String = ["security", "reboot"]
+        .into_iter()
+        .filter(|class| classes.contains(class))
+        .map(|class| format!(", {class}"))
+        .collect();
+    match count {
+        1 => writeln!(out, "1 upgrade{notes}")?,
+        _ => writeln!(out, "{count} upgrades{notes}")?,
+    }
+    writeln!(out, "{count}")?;
+    if classes.contains(&"security") {
+        writeln!(out, "{SECURITY_COLOR}")?;
+    } else if classes.contains(&"reboot") {
+        writeln!(out, "{REBOOT_COLOR}")?;
+    } else if classes.contains(&"many") {
+        writeln!(out, "{MANY_COLOR}")?;
+    }
+    Ok(())
+}
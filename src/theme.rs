@@ -0,0 +1,257 @@
+//! Colors and styles for the text output
+//!
+//! The default theme uses the colors checkupgrades has always had. It can be changed with
+//! `$CHECKUPGRADES_COLORS`, a colon separated list of `key=SGR` entries like `LS_COLORS`, e.g.
+//! `old=1;31:new=38;5;42:repo/core=38;2;255;128;0:repo/*-testing=4;33`. The keys are `old`,
+//! `new`, `common` (the unchanged part of versions), `note`, `header`, `totals`, `warning`,
+//! `security`, `unknown` (packages without a repo), and `repo/GLOB` where the glob matches repo
+//! names.
+
+use std::sync::OnceLock;
+
+use anyhow::{anyhow, bail, Context, Result};
+use owo_colors::{AnsiColors, Effect, Style, XtermColors};
+
+use crate::{glob_match, Repo};
+
+/// The environment variable which overrides the default theme
+pub const ENV_VAR: &str = "CHECKUPGRADES_COLORS";
+
+static THEME: OnceLock<Theme> = OnceLock::new();
+
+/// The styles used for each part of the output
+#[derive(Debug, Clone)]
+pub struct Theme {
+    /// Repo name globs and their styles, the first match wins
+    repos: Vec<(String, Style)>,
+    /// How many of `repos` were set by the user, they go before the defaults
+    user_repos: usize,
+    /// Packages whose repo isn't known
+    pub unknown: Style,
+    /// The changed part of the old version
+    pub old: Style,
+    /// The changed part of the new version
+    pub new: Style,
+    /// The part of the versions that didn't change
+    pub common: Style,
+    /// Secondary information: version classes, `[cached]`, group sizes, subtotals
+    pub note: Style,
+    /// Column and section headers
+    pub header: Style,
+    /// The numbers in the summary
+    pub totals: Style,
+    /// The `Warning:` prefix of warnings, and warning markers in the output
+    pub warning: Style,
+    /// The security issues fixed by upgrades
+    pub security: Style,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        let fg = |color| Style::new().color(color);
+        let repos = [
+            ("core", fg(AnsiColors::Magenta)),
+            ("extra", fg(AnsiColors::Blue)),
+            ("community", fg(AnsiColors::Red)),
+            ("multilib", fg(AnsiColors::Green)),
+            ("*", fg(AnsiColors::Cyan)),
+        ];
+        Self {
+            repos: repos.into_iter().map(|(glob, style)| (glob.to_owned(), style)).collect(),
+            user_repos: 0,
+            unknown: fg(AnsiColors::White),
+            old: fg(AnsiColors::Red),
+            new: fg(AnsiColors::Green),
+            common: Style::new(),
+            note: Style::new().dimmed(),
+            header: Style::new().bold(),
+            totals: Style::new(),
+            warning: fg(AnsiColors::Yellow).bold(),
+            security: fg(AnsiColors::Red).bold(),
+        }
+    }
+}
+
+impl Theme {
+    /// The style for the name of `repo`
+    pub fn repo(&self, repo: &Repo) -> Style {
+        if *repo == Repo::Unknown {
+            return self.unknown;
+        }
+        let name = repo.as_str();
+        self.repos
+            .iter()
+            .find(|(glob, _)| glob_match(glob, name))
+            .map_or(Style::new(), |(_, style)| *style)
+    }
+
+    /// Set the style for `key`, see the module docs for the keys
+    pub fn set(&mut self, key: &str, style: Style) -> Result<()> {
+        if let Some(glob) = key.strip_prefix("repo/") {
+            self.repos.insert(self.user_repos, (glob.to_owned(), style));
+            self.user_repos += 1;
+            return Ok(());
+        }
+        let field = match key {
+            "unknown" => &mut self.unknown,
+            "old" => &mut self.old,
+            "new" => &mut self.new,
+            "common" => &mut self.common,
+            "note" => &mut self.note,
+            "header" => &mut self.header,
+            "totals" => &mut self.totals,
+            "warning" => &mut self.warning,
+            "security" => &mut self.security,
+            _ => bail!("unknown theme key {key:?}"),
+        };
+        *field = style;
+        Ok(())
+    }
+
+    /// Apply a list of `key=SGR` entries separated by colons. Invalid entries are skipped and
+    /// returned, so that one typo doesn't lose the whole theme.
+    pub fn apply_spec(&mut self, spec: &str) -> Vec<anyhow::Error> {
+        let mut errors = Vec::new();
+        for entry in spec.split(':').filter(|e| !e.is_empty()) {
+            let result = entry
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected key=style"))
+                .and_then(|(key, sgr)| self.set(key, parse_sgr(sgr)?));
+            if let Err(err) = result {
+                errors.push(err.context(format!("invalid entry {entry:?}")));
+            }
+        }
+        errors
+    }
+}
+
+/// Parse a style from SGR parameters as used by `LS_COLORS`, e.g. `1;31`, `38;5;208` for a 256
+/// color palette entry, or `38;2;255;128;0` for truecolor.
+pub fn parse_sgr(sgr: &str) -> Result<Style> {
+    let mut codes = sgr
+        .split(';')
+        .filter(|c| !c.is_empty())
+        .map(|code| code.parse::<u8>().with_context(|| format!("invalid SGR parameter {code:?}")));
+    let mut style = Style::new();
+    while let Some(code) = codes.next() {
+        let code = code?;
+        style = match code {
+            0 => Style::new(),
+            1 => style.bold(),
+            2 => style.effect(Effect::Dimmed),
+            3 => style.effect(Effect::Italic),
+            4 => style.effect(Effect::Underline),
+            5 => style.effect(Effect::Blink),
+            7 => style.effect(Effect::Reversed),
+            8 => style.effect(Effect::Hidden),
+            9 => style.effect(Effect::Strikethrough),
+            30..=37 => style.color(ansi_color(code - 30, false)),
+            40..=47 => style.on_color(ansi_color(code - 40, false)),
+            90..=97 => style.color(ansi_color(code - 90, true)),
+            100..=107 => style.on_color(ansi_color(code - 100, true)),
+            38 | 48 => {
+                let mut next = || codes.next().context("incomplete extended color")?;
+                let background = code == 48;
+                match next()? {
+                    5 => {
+                        let color = XtermColors::from(next()?);
+                        if background {
+                            style.on_color(color)
+                        } else {
+                            style.color(color)
+                        }
+                    }
+                    2 => {
+                        let (r, g, b) = (next()?, next()?, next()?);
+                        if background {
+                            style.on_truecolor(r, g, b)
+                        } else {
+                            style.truecolor(r, g, b)
+                        }
+                    }
+                    kind => bail!("unsupported extended color type {kind}"),
+                }
+            }
+            _ => bail!("unsupported SGR parameter {code}"),
+        };
+    }
+    Ok(style)
+}
+
+/// One of the 8 basic ANSI colors, or its bright variant
+fn ansi_color(n: u8, bright: bool) -> AnsiColors {
+    use AnsiColors::*;
+    let colors = if bright {
+        [
+            BrightBlack,
+            BrightRed,
+            BrightGreen,
+            BrightYellow,
+            BrightBlue,
+            BrightMagenta,
+            BrightCyan,
+            BrightWhite,
+        ]
+    } else {
+        [Black, Red, Green, Yellow, Blue, Magenta, Cyan, White]
+    };
+    colors[usize::from(n)]
+}
+
+/// Set the theme used by `get`. Only the first call has an effect.
+pub fn init(theme: Theme) {
+    let _ = THEME.set(theme);
+}
+
+/// The current theme, the default one if `init` hasn't been called
+pub fn get() -> &'static Theme {
+    THEME.get_or_init(Theme::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use owo_colors::OwoColorize;
+
+    use super::*;
+
+    fn render(style: Style) -> String {
+        "x".style(style).to_string()
+    }
+
+    #[test]
+    fn sgr() {
+        let cases = [
+            ("1;31", Style::new().bold().red()),
+            ("0", Style::new()),
+            ("", Style::new()),
+            // empty parameters are skipped
+            ("4;;93", Style::new().underline().bright_yellow()),
+            ("1;0;32", Style::new().green()),
+            ("38;5;208", Style::new().color(XtermColors::from(208))),
+            ("48;2;255;128;0", Style::new().on_truecolor(255, 128, 0)),
+            ("2;3;9;44", Style::new().dimmed().italic().strikethrough().on_blue()),
+        ];
+        for (sgr, style) in cases {
+            assert_eq!(render(parse_sgr(sgr).unwrap()), render(style), "{sgr:?}");
+        }
+        for sgr in ["31;x", "256", "-1", "6", "38", "38;5", "38;2;255;128", "38;7;1", "1:31"] {
+            assert!(parse_sgr(sgr).is_err(), "{sgr:?}");
+        }
+    }
+
+    #[test]
+    fn keys() {
+        let mut theme = Theme::default();
+        assert!(theme.set("new", Style::new().bold()).is_ok());
+        assert!(theme.set("repo/*-testing", Style::new().yellow()).is_ok());
+        assert!(theme.set("bogus", Style::new()).is_err());
+        assert!(theme.set("repo", Style::new()).is_err());
+        assert_eq!(render(theme.new), render(Style::new().bold()));
+        assert_eq!(render(theme.repo(&Repo::from("core-testing"))), render(Style::new().yellow()));
+        assert_eq!(render(theme.repo(&Repo::from("core"))), render(Style::new().magenta()));
+
+        let errors = theme.apply_spec("old=1;31:bogus=1:header:note=1;x:");
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert_eq!(render(theme.old), render(Style::new().bold().red()));
+    }
+}
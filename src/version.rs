@@ -0,0 +1,387 @@
+//! Classify pacman version changes by how significant they are
+//!
+//! Pacman versions have the form `[epoch:]pkgver-pkgrel`. An upgrade is classified by the most
+//! significant part that changed: the epoch, the first/second/later component of pkgver, or only
+//! the pkgrel (a rebuild). Development snapshot versions from VCS packages or date-based versions
+//! don't follow major.minor.patch so they get their own class.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use clap::builder::PossibleValue;
+use serde::{Serialize, Serializer};
+
+use crate::regex;
+
+/// The kind of version change in an upgrade, ordered from most to least significant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VersionClass {
+    Epoch,
+    Major,
+    Minor,
+    Patch,
+    Vcs,
+    Rebuild,
+}
+
+impl VersionClass {
+    pub const ALL: [Self; 6] =
+        [Self::Epoch, Self::Major, Self::Minor, Self::Patch, Self::Vcs, Self::Rebuild];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Epoch => "epoch",
+            Self::Major => "major",
+            Self::Minor => "minor",
+            Self::Patch => "patch",
+            Self::Vcs => "vcs",
+            Self::Rebuild => "rebuild",
+        }
+    }
+}
+
+impl fmt::Display for VersionClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+impl Serialize for VersionClass {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl clap::ValueEnum for VersionClass {
+    fn value_variants<'a>() -> &'a [Self] {
+        &Self::ALL
+    }
+
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        let value = PossibleValue::new(self.as_str());
+        Some(match self {
+            Self::Epoch => value.help("The epoch changed"),
+            Self::Major => value.help("The first pkgver component changed"),
+            Self::Minor => value.help("The second pkgver component changed"),
+            Self::Patch => value.help("A later pkgver component changed"),
+            Self::Vcs => value.help("VCS snapshot or date based version changed"),
+            Self::Rebuild => value.alias("pkgrel").help("Only the pkgrel changed"),
+        })
+    }
+}
+
+/// Split a version into `(epoch, pkgver, pkgrel)`. A missing epoch is `"0"`, a missing pkgrel is
+/// empty.
+pub fn split_version(version: &str) -> (&str, &str, &str) {
+    let (epoch, rest) = version.split_once(':').unwrap_or(("0", version));
+    let (pkgver, pkgrel) = rest.rsplit_once('-').unwrap_or((rest, ""));
+    (epoch, pkgver, pkgrel)
+}
+
+/// Compare two parts of versions like pacman's `rpmvercmp`. Runs of digits are compared as
+/// numbers and runs of letters alphabetically, with a number newer than letters, and separators
+/// only matter by their length.
+fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (a, b) = (a.as_bytes(), b.as_bytes());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        let (sep_a, sep_b) = (i, j);
+        while i < a.len() && !a[i].is_ascii_alphanumeric() {
+            i += 1;
+        }
+        while j < b.len() && !b[j].is_ascii_alphanumeric() {
+            j += 1;
+        }
+        if i == a.len() || j == b.len() {
+            break;
+        }
+        if i - sep_a != j - sep_b {
+            return (i - sep_a).cmp(&(j - sep_b));
+        }
+
+        let numeric = a[i].is_ascii_digit();
+        let segment_end = |s: &[u8], mut k: usize| {
+            while k < s.len()
+                && (if numeric { s[k].is_ascii_digit() } else { s[k].is_ascii_alphabetic() })
+            {
+                k += 1;
+            }
+            k
+        };
+        let (end_a, end_b) = (segment_end(a, i), segment_end(b, j));
+        // the segments are of different kinds
+        if end_b == j {
+            return if numeric { Ordering::Greater } else { Ordering::Less };
+        }
+        let (mut seg_a, mut seg_b) = (&a[i..end_a], &b[j..end_b]);
+        if numeric {
+            while let [b'0', rest @ ..] = seg_a {
+                seg_a = rest;
+            }
+            while let [b'0', rest @ ..] = seg_b {
+                seg_b = rest;
+            }
+            if seg_a.len() != seg_b.len() {
+                return seg_a.len().cmp(&seg_b.len());
+            }
+        }
+        match seg_a.cmp(seg_b) {
+            Ordering::Equal => (i, j) = (end_a, end_b),
+            ordering => return ordering,
+        }
+    }
+
+    // one ran out of segments: `1.0` < `1.0.1`, but `1.0alpha` < `1.0`
+    if i == a.len() && j == b.len() {
+        Ordering::Equal
+    } else if (i == a.len() && !b[j].is_ascii_alphabetic())
+        || (i < a.len() && a[i].is_ascii_alphabetic())
+    {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+/// Compare two versions like `vercmp`: by epoch, then pkgver, then pkgrel if both have one
+pub fn vercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+    let (epoch_a, pkgver_a, pkgrel_a) = split_version(a);
+    let (epoch_b, pkgver_b, pkgrel_b) = split_version(b);
+    rpmvercmp(epoch_a, epoch_b).then_with(|| rpmvercmp(pkgver_a, pkgver_b)).then_with(|| {
+        if pkgrel_a.is_empty() || pkgrel_b.is_empty() {
+            Ordering::Equal
+        } else {
+            rpmvercmp(pkgrel_a, pkgrel_b)
+        }
+    })
+}
+
+/// Whether a pkgver looks like a VCS snapshot (`r1234.gabcdef1`, `1.2.r5.g0123abc`) or a date
+/// (`20240131`).
+fn is_vcs_or_date(pkgver: &str) -> bool {
+    let re = regex!(
+        r"(?x)
+        (^|[.+_])r[0-9]+([.+_]|$)      # revision count
+        | [.+_]g[0-9a-f]{7,}([.+_]|$)  # abbreviated git hash
+        | (^|[^0-9])(19|20)[0-9]{6}([^0-9]|$)  # YYYYMMDD date
+    "
+    );
+    re.is_match(pkgver)
+}
+
+/// Whether `c` separates the segments of a pacman version
+fn is_separator(c: char) -> bool {
+    matches!(c, ':' | '.' | '-' | '+' | '_')
+}
+
+/// Find the length in bytes of the common prefix of two versions, for highlighting the part that
+/// changed.
+///
+/// The prefix always ends on a segment boundary (after a `:`, `.`, `-`, `+`, or `_`) so that whole
+/// segments are highlighted, e.g. `1.9.10` -> `1.10.0` has the common prefix `1.` rather than
+/// `1.1`. Works on chars, so the result is a valid char boundary in both strings.
+pub fn common_prefix_len(old: &str, new: &str) -> usize {
+    let mut common = 0;
+    let mut new_chars = new.chars();
+    for (i, oc) in old.char_indices() {
+        match new_chars.next() {
+            Some(nc) if nc == oc => {
+                if is_separator(oc) {
+                    common = i + oc.len_utf8();
+                }
+            }
+            // the versions differ here
+            Some(_) => return common,
+            // new is a prefix of old, it's a whole segment if old continues with a separator
+            None => return if is_separator(oc) { i } else { common },
+        }
+    }
+
+    match new_chars.next() {
+        // old is a prefix of new
+        Some(nc) if is_separator(nc) => old.len(),
+        Some(_) => common,
+        // identical versions, nothing to highlight
+        None => old.len(),
+    }
+}
+
+/// Classify the change from version `old` to version `new`.
+pub fn classify(old: &str, new: &str) -> VersionClass {
+    let (old_epoch, old_pkgver, _) = split_version(old);
+    let (new_epoch, new_pkgver, _) = split_version(new);
+
+    if old_epoch != new_epoch {
+        return VersionClass::Epoch;
+    }
+    if old_pkgver == new_pkgver {
+        return VersionClass::Rebuild;
+    }
+    if is_vcs_or_date(old_pkgver) || is_vcs_or_date(new_pkgver) {
+        return VersionClass::Vcs;
+    }
+
+    let is_sep = |c: char| matches!(c, '.' | '+' | '_');
+    let changed = old_pkgver
+        .split(is_sep)
+        .zip(new_pkgver.split(is_sep))
+        .position(|(o, n)| o != n)
+        // one version is a prefix of the other, e.g. 1.2 -> 1.2.1
+        .unwrap_or_else(|| old_pkgver.split(is_sep).count().min(new_pkgver.split(is_sep).count()));
+
+    match changed {
+        0 => VersionClass::Major,
+        1 => VersionClass::Minor,
+        _ => VersionClass::Patch,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vercmp_matches_pacman() {
+        use Ordering::*;
+        // the order libalpm's alpm_pkg_vercmp, and so vercmp(8), gives
+        let cases = [
+            ("1.5.0", "1.5.0", Equal),
+            ("1.5.1", "1.5.0", Greater),
+            ("1.5.1", "1.5", Greater),
+            ("1.5.0-1", "1.5.0-1", Equal),
+            ("1.5.0-1", "1.5.0-2", Less),
+            ("1.5.0-1", "1.5.1-1", Less),
+            ("1.5.0-2", "1.5.1-1", Less),
+            ("1.5-1", "1.5", Equal),
+            ("1.1-1", "1.1", Equal),
+            ("1.0-1", "1.0-1.1", Less),
+            ("1.0-1.1", "1.1-1", Less),
+            ("1.5b-1", "1.5-1", Less),
+            ("1.5b", "1.5", Less),
+            ("1.5b-1", "1.5", Less),
+            ("1.5b", "1.5.1", Less),
+            ("1.0a", "1.0alpha", Less),
+            ("1.0alpha", "1.0b", Less),
+            ("1.0b", "1.0beta", Less),
+            ("1.0beta", "1.0rc", Less),
+            ("1.0rc", "1.0", Less),
+            ("1.5.a", "1.5", Greater),
+            ("1.5.b", "1.5.a", Greater),
+            ("1.5.1", "1.5.b", Greater),
+            ("1.5.b-1", "1.5.b", Equal),
+            ("1.5-1", "1.5.b", Less),
+            ("1.0a", "1.0.a", Less),
+            ("1.0.a", "1.0a", Greater),
+            ("1.0.a", "1.0", Greater),
+            ("1.0.", "1.0.a", Greater),
+            ("1.0..1", "1.0.1", Greater),
+            ("1.0_1", "1.0.1", Equal),
+            ("1.0+1", "1.0.1", Equal),
+            ("0:1.0", "0:1.0", Equal),
+            ("0:1.0", "0:1.1", Less),
+            ("1:1.0", "0:1.0", Greater),
+            ("1:1.0", "0:1.1", Greater),
+            ("1:1.0", "2:1.1", Less),
+            ("0:1.0", "1.0", Equal),
+            ("1:1.0", "1.0", Greater),
+            ("1:1.0", "1.1", Greater),
+            ("1.1", "1:1.0", Less),
+            ("2:1.0-1", "10:0.1-1", Less),
+            ("1.0", "1.00", Equal),
+            ("1.01", "1.1", Equal),
+            ("1.002", "1.1", Greater),
+            ("10", "9", Greater),
+            ("a", "b", Less),
+            ("1.0", "1.0a", Greater),
+            ("1.0.1", "1.0a", Greater),
+            ("r1234.gabcdef1-1", "r1235.g0123456-1", Less),
+            ("1.2.r5.g0123abc-1", "1.2.r12.gfedcba9-1", Less),
+            ("20240131-1", "20240201-1", Less),
+            ("2024.01.31-1", "2024.1.31-1", Equal),
+            ("6.1.1.arch1-1", "6.1.1.arch2-1", Less),
+            ("6.10-1", "6.9-1", Greater),
+            ("1.0-1", "1.0-10", Less),
+            ("1.0-2", "1.0-10", Less),
+            ("1.0-2.1", "1.0-2", Greater),
+        ];
+        for (a, b, ordering) in cases {
+            assert_eq!(vercmp(a, b), ordering, "vercmp {a} {b}");
+            assert_eq!(vercmp(b, a), ordering.reverse(), "vercmp {b} {a}");
+        }
+    }
+
+    #[test]
+    fn classify_changes() {
+        use VersionClass::*;
+        let cases = [
+            ("1.2.3-1", "1:1.0.0-1", Epoch),
+            ("1:1.2.3-1", "2:1.2.3-1", Epoch),
+            ("1.2.3-1", "2.0.0-1", Major),
+            ("1.2.3-1", "1.3.0-1", Minor),
+            ("1.2.3-1", "1.2.4-1", Patch),
+            ("1.2.3.4-1", "1.2.3.5-1", Patch),
+            // one is a prefix of the other
+            ("1.2-1", "1.2.1-1", Patch),
+            ("1-1", "1.1-1", Minor),
+            ("1.9.10-1", "1.10.0-1", Minor),
+            ("6.1.1.arch1-1", "6.1.1.arch2-1", Patch),
+            // pkgrel only
+            ("1.2.3-1", "1.2.3-2", Rebuild),
+            ("1.2.3-1", "1.2.3-1.1", Rebuild),
+            ("1:1.2.3-1", "1:1.2.3-2", Rebuild),
+            // VCS and dates
+            ("r1234.gabcdef1-1", "r1240.g0123456-1", Vcs),
+            ("1.2.r5.g0123abc-1", "1.2.r12.gfedcba9-1", Vcs),
+            ("0.9-1", "1.0.r3.g1234567-1", Vcs),
+            ("20240131-1", "20240201-1", Vcs),
+            ("1.0_20240131-1", "1.0_20240201-1", Vcs),
+            // not a date, too few digits
+            ("2024.1-1", "2024.2-1", Minor),
+        ];
+        for (old, new, class) in cases {
+            assert_eq!(classify(old, new), class, "{old} -> {new}");
+        }
+    }
+
+    #[test]
+    fn common_prefixes() {
+        let cases = [
+            ("1.2.3-1", "1.2.4-1", "1.2."),
+            ("1.9.10-1", "1.10.0-1", "1."),
+            ("1.2.3-1", "1.2.3-2", "1.2.3-"),
+            ("1:1.0-1", "2:1.0-1", ""),
+            ("1.2", "1.23", "1."),
+            ("1.2", "1.2.1", "1.2"),
+            ("1.2.1", "1.2", "1.2"),
+            ("1.2", "1.2", "1.2"),
+            ("", "1.0", ""),
+            // multibyte chars, which may share their leading bytes
+            ("1.0-α", "1.0-β", "1.0-"),
+            ("1.€.0", "1.₤.0", "1."),
+            ("ä.1", "ä.2", "ä."),
+            ("1.0ü-1", "1.0ü-2", "1.0ü-"),
+            ("é1.0", "é2.0", ""),
+            ("1.ß", "1.ß.1", "1.ß"),
+        ];
+        for (old, new, prefix) in cases {
+            let len = common_prefix_len(old, new);
+            assert!(old.is_char_boundary(len) && new.is_char_boundary(len), "{old} -> {new}");
+            assert_eq!(&old[..len], prefix, "{old} -> {new}");
+            assert_eq!(&new[..len], prefix, "{old} -> {new}");
+        }
+    }
+
+    #[test]
+    fn split_versions() {
+        assert_eq!(split_version("1:2.3-4"), ("1", "2.3", "4"));
+        assert_eq!(split_version("2.3-4"), ("0", "2.3", "4"));
+        assert_eq!(split_version("2.3"), ("0", "2.3", ""));
+        assert_eq!(split_version("1.0-rc1-2"), ("0", "1.0-rc1", "2"));
+    }
+}